@@ -5,18 +5,28 @@
  * LICENSE file in the root directory of this source tree.
  */
 
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::io::Write;
 use std::iter;
 use std::iter::once;
 use std::mem;
+use std::panic;
+use std::panic::AssertUnwindSafe;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicI32;
+use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
+use std::time::Duration;
 
 use clap::Parser;
+use crossbeam_channel::Sender;
+use crossbeam_channel::select;
+use crossbeam_channel::unbounded;
 use dupe::Dupe;
 use lsp_server::Connection;
 use lsp_server::ErrorCode;
@@ -26,6 +36,12 @@ use lsp_server::Request;
 use lsp_server::RequestId;
 use lsp_server::Response;
 use lsp_server::ResponseError;
+use lsp_types::CodeAction;
+use lsp_types::CodeActionKind;
+use lsp_types::CodeActionOrCommand;
+use lsp_types::CodeActionParams;
+use lsp_types::CodeActionProviderCapability;
+use lsp_types::CompletionItem;
 use lsp_types::CompletionList;
 use lsp_types::CompletionOptions;
 use lsp_types::CompletionParams;
@@ -34,9 +50,17 @@ use lsp_types::ConfigurationItem;
 use lsp_types::ConfigurationParams;
 use lsp_types::Diagnostic;
 use lsp_types::DidChangeTextDocumentParams;
+use lsp_types::DidChangeWatchedFilesParams;
+use lsp_types::DidChangeWatchedFilesRegistrationOptions;
 use lsp_types::DidCloseTextDocumentParams;
 use lsp_types::DidOpenTextDocumentParams;
 use lsp_types::DidSaveTextDocumentParams;
+use lsp_types::DocumentSymbol;
+use lsp_types::DocumentSymbolParams;
+use lsp_types::DocumentSymbolResponse;
+use lsp_types::Documentation;
+use lsp_types::FileSystemWatcher;
+use lsp_types::GlobPattern;
 use lsp_types::GotoDefinitionParams;
 use lsp_types::GotoDefinitionResponse;
 use lsp_types::Hover;
@@ -52,27 +76,52 @@ use lsp_types::MarkupContent;
 use lsp_types::MarkupKind;
 use lsp_types::NumberOrString;
 use lsp_types::OneOf;
+use lsp_types::ProgressParams;
+use lsp_types::ProgressParamsValue;
 use lsp_types::PublishDiagnosticsParams;
 use lsp_types::Range;
+use lsp_types::Registration;
+use lsp_types::RegistrationParams;
 use lsp_types::ServerCapabilities;
+use lsp_types::SymbolInformation;
+use lsp_types::SymbolKind;
+use lsp_types::TextDocumentContentChangeEvent;
 use lsp_types::TextDocumentSyncCapability;
 use lsp_types::TextDocumentSyncKind;
 use lsp_types::TextEdit;
 use lsp_types::Url;
+use lsp_types::WatchKind;
+use lsp_types::WorkDoneProgress;
+use lsp_types::WorkDoneProgressBegin;
+use lsp_types::WorkDoneProgressCreateParams;
+use lsp_types::WorkDoneProgressEnd;
+use lsp_types::WorkspaceEdit;
+use lsp_types::WorkspaceSymbolParams;
 use lsp_types::notification::Cancel;
 use lsp_types::notification::DidChangeConfiguration;
 use lsp_types::notification::DidChangeTextDocument;
+use lsp_types::notification::DidChangeWatchedFiles;
 use lsp_types::notification::DidCloseTextDocument;
 use lsp_types::notification::DidOpenTextDocument;
 use lsp_types::notification::DidSaveTextDocument;
+use lsp_types::notification::Notification as _;
+use lsp_types::notification::Progress;
 use lsp_types::notification::PublishDiagnostics;
+use lsp_types::request::CodeActionRequest;
 use lsp_types::request::Completion;
+use lsp_types::request::DocumentSymbolRequest;
 use lsp_types::request::GotoDefinition;
 use lsp_types::request::HoverRequest;
 use lsp_types::request::InlayHintRequest;
+use lsp_types::request::RegisterCapability;
+use lsp_types::request::ResolveCompletionItem;
+use lsp_types::request::WorkDoneProgressCreate;
 use lsp_types::request::WorkspaceConfiguration;
+use lsp_types::request::WorkspaceSymbolRequest;
 use ruff_source_file::SourceLocation;
 use ruff_text_size::TextSize;
+use serde::Deserialize;
+use serde::Serialize;
 use serde::de::DeserializeOwned;
 use starlark_map::small_map::Iter;
 use starlark_map::small_map::SmallMap;
@@ -106,6 +155,21 @@ pub struct Args {
     pub(crate) search_path: Vec<PathBuf>,
     #[clap(long = "site-package-path", env = clap_env("SITE_PACKAGE_PATH"))]
     pub(crate) site_package_path: Vec<PathBuf>,
+    /// Directory to track a fingerprint of the workspace's search paths between runs.
+    /// This is not a checked-state cache: the fingerprint is compared and logged (see
+    /// `Server::new`'s `CacheFingerprint` handling) and rewritten once per run, and every
+    /// run does a full recheck whether or not it matches. Defaults to a `.pyrefly_cache`
+    /// directory under the first workspace folder.
+    #[clap(long = "cache-dir", env = clap_env("CACHE_DIR"))]
+    pub(crate) cache_dir: Option<PathBuf>,
+    /// Disable fingerprint tracking entirely (see `cache_dir`).
+    #[clap(long = "no-cache", env = clap_env("NO_CACHE"))]
+    pub(crate) no_cache: bool,
+    /// Append a machine-readable JSON record of every diagnostic to this file on each
+    /// recheck (see `JsonDiagnosticsLog`), alongside the normal `textDocument/publishDiagnostics`
+    /// notifications. Off by default so text/LSP output is unchanged.
+    #[clap(long = "diagnostic-json-log", env = clap_env("DIAGNOSTIC_JSON_LOG"))]
+    pub(crate) diagnostic_json_log: Option<PathBuf>,
 }
 
 /// `IDETransactionManager` aims to always produce a transaction that contains the up-to-date
@@ -162,14 +226,263 @@ impl<'a> IDETransactionManager<'a> {
     }
 }
 
+thread_local! {
+    /// Method (and, for requests, id) of the message currently executing on this
+    /// thread. Surfaced in the panic log line so a crash can be traced back to the
+    /// LSP message that triggered it.
+    static CURRENT_MESSAGE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Bounded pool of worker threads that run read-only IDE request handlers (see
+/// `RequestDispatcher::on`). Requests are pushed onto a shared queue and picked up by
+/// whichever of a fixed number of long-lived threads is free, so a burst of requests
+/// (e.g. an editor re-requesting `completion`/`hover` on every keystroke while the user
+/// types quickly) queues up instead of spawning one native OS thread per request.
+struct ReadRequestPool {
+    jobs: Sender<Box<dyn FnOnce() + Send>>,
+}
+
+impl ReadRequestPool {
+    /// Spawns `size` worker threads (at least one), each looping on the shared job
+    /// queue until it's dropped.
+    fn new(size: usize) -> Self {
+        let (jobs, jobs_rx) = unbounded::<Box<dyn FnOnce() + Send>>();
+        for _ in 0..size.max(1) {
+            let jobs_rx = jobs_rx.clone();
+            std::thread::spawn(move || {
+                for job in jobs_rx {
+                    job();
+                }
+            });
+        }
+        Self { jobs }
+    }
+
+    /// Queues `job` to run on whichever worker thread picks it up next.
+    fn spawn(&self, job: impl FnOnce() + Send + 'static) {
+        // The channel only closes once every worker thread has exited, which only
+        // happens after `self.jobs` itself is dropped, so sending here can't fail in
+        // practice; ignore the error rather than panicking the main loop over it.
+        let _ = self.jobs.send(Box::new(job));
+    }
+}
+
+fn panic_payload_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        (*s).to_owned()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic payload".to_owned()
+    }
+}
+
+/// Dispatches an incoming `Message::Request` to the first matching `.on::<T>()`
+/// handler. All of today's requests are read-only, so each handler runs against a
+/// `Snapshot` on a `ReadRequestPool` worker thread (see `Server::snapshot`), inside `catch_unwind`
+/// so a panic in one handler (e.g. a bug in `goto_definition`) can't unwind through the
+/// main message loop and kill the server. The response is delivered back to the main
+/// loop through `Server::worker_results` rather than sent directly, since the main
+/// thread alone decides whether the content has since moved on (see
+/// `Server::process_worker_read_result`). Modeled on rust-analyzer's `RequestDispatcher`.
+struct RequestDispatcher<'s> {
+    server: &'s Server,
+    request: Request,
+    handled: bool,
+}
+
+impl<'s> RequestDispatcher<'s> {
+    fn new(server: &'s Server, request: Request) -> Self {
+        Self {
+            server,
+            request,
+            handled: false,
+        }
+    }
+
+    fn on<T, R>(
+        mut self,
+        handler: impl FnOnce(&Snapshot, &Transaction<'_>, T::Params) -> anyhow::Result<R> + Send + 'static,
+    ) -> Self
+    where
+        T: lsp_types::request::Request,
+        T::Params: DeserializeOwned + Send + 'static,
+        R: serde::Serialize + Send + 'static,
+    {
+        if self.handled {
+            return self;
+        }
+        let Some(params) = as_request::<T>(&self.request) else {
+            return self;
+        };
+        self.handled = true;
+        let id = self.request.id.clone();
+        let request = self.request.clone();
+        let revision_at_start = self.server.content_revision();
+        let snapshot = self.server.snapshot();
+        let worker_results = self.server.worker_results.clone();
+        let canceled_requests = self.server.canceled_requests.dupe();
+        // If this request is about the same document as one we're already working on,
+        // the older one is obsolete the moment this one lands (e.g. the user moved the
+        // cursor again before `hover` replied to the first move) — supersede it rather
+        // than let two queries race to answer the same question.
+        let document = document_uri_from_params(&self.request.params);
+        if let Some(document) = &document
+            && let Some(superseded) = self
+                .server
+                .in_flight_requests
+                .lock()
+                .insert(document.clone(), id.clone())
+            && superseded != id
+        {
+            canceled_requests.lock().insert(superseded);
+        }
+        self.server.read_request_pool.spawn(move || {
+            CURRENT_MESSAGE.with(|c| *c.borrow_mut() = Some(format!("{} ({id})", T::METHOD)));
+            // The request may have been canceled (explicitly, or by a newer request
+            // superseding it) while it was queued for a worker thread; save the work of
+            // actually running the query if so. This can't interrupt a query that's
+            // already running, since `Transaction` doesn't expose a cancellation
+            // checkpoint to poll — `Server::process_worker_read_result` is the backstop
+            // for that case.
+            if canceled_requests.lock().contains(&id) {
+                CURRENT_MESSAGE.with(|c| *c.borrow_mut() = None);
+                let message = format!("Request {id} is canceled");
+                eprintln!("{message}");
+                let response =
+                    Response::new_err(id.clone(), ErrorCode::RequestCanceled as i32, message);
+                let _ = worker_results.send(WorkerReadResult {
+                    id,
+                    request,
+                    revision_at_start,
+                    document,
+                    response,
+                });
+                return;
+            }
+            let transaction = snapshot.state.transaction();
+            let result =
+                panic::catch_unwind(AssertUnwindSafe(|| handler(&snapshot, &transaction, params)));
+            CURRENT_MESSAGE.with(|c| *c.borrow_mut() = None);
+            let response = match result {
+                Ok(result) => new_response(id.clone(), result),
+                Err(payload) => {
+                    let message = panic_payload_message(payload.as_ref());
+                    eprintln!("Request {} panicked: {message}", T::METHOD);
+                    Response::new_err(id.clone(), ErrorCode::InternalError as i32, message)
+                }
+            };
+            // The receiver lives in the main loop for as long as `server` does, so a send
+            // error here just means the server is already shutting down.
+            let _ = worker_results.send(WorkerReadResult {
+                id,
+                request,
+                revision_at_start,
+                document,
+                response,
+            });
+        });
+        self
+    }
+
+    fn finish(self) {
+        if !self.handled {
+            eprintln!("Unhandled request: {:?}", self.request);
+        }
+    }
+}
+
+/// Dispatches an incoming `Message::Notification` the same way `RequestDispatcher`
+/// dispatches requests. A notification has no response, so a panicking handler is
+/// just logged rather than turned into an error reply.
+struct NotificationDispatcher<'s, 'a> {
+    server: &'s Server,
+    ide_transaction_manager: &'s mut IDETransactionManager<'a>,
+    notification: Notification,
+    handled: bool,
+    result: anyhow::Result<()>,
+}
+
+impl<'s, 'a> NotificationDispatcher<'s, 'a> {
+    fn new(
+        server: &'s Server,
+        ide_transaction_manager: &'s mut IDETransactionManager<'a>,
+        notification: Notification,
+    ) -> Self {
+        Self {
+            server,
+            ide_transaction_manager,
+            notification,
+            handled: false,
+            result: Ok(()),
+        }
+    }
+
+    fn on<T>(
+        mut self,
+        handler: impl FnOnce(&'s Server, &mut IDETransactionManager<'a>, T::Params) -> anyhow::Result<()>,
+    ) -> Self
+    where
+        T: lsp_types::notification::Notification,
+        T::Params: DeserializeOwned,
+    {
+        if self.handled {
+            return self;
+        }
+        let Some(params) = as_notification::<T>(&self.notification) else {
+            return self;
+        };
+        self.handled = true;
+        CURRENT_MESSAGE.with(|c| *c.borrow_mut() = Some(T::METHOD.to_owned()));
+        let server = self.server;
+        let ide_transaction_manager = &mut *self.ide_transaction_manager;
+        let result =
+            panic::catch_unwind(AssertUnwindSafe(|| handler(server, ide_transaction_manager, params)));
+        CURRENT_MESSAGE.with(|c| *c.borrow_mut() = None);
+        self.result = match result {
+            Ok(result) => result,
+            Err(payload) => {
+                let message = panic_payload_message(payload.as_ref());
+                eprintln!("Notification {} panicked: {message}", T::METHOD);
+                Ok(())
+            }
+        };
+        self
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        if !self.handled {
+            eprintln!("Unhandled notification: {:?}", self.notification);
+        }
+        self.result
+    }
+}
+
 /// Events that must be handled by the server as soon as possible.
 /// The server will clear the queue of such event after processing each LSP message.
 enum ImmediatelyHandledEvent {
     /// Notify the server that recheck finishes, so server can revalidate all in-memory content
     /// based on the latest `State`.
     RecheckFinished,
+    /// The quiet period since the last `workspace/didChangeWatchedFiles` event has elapsed, so
+    /// the accumulated paths should be invalidated together (see `did_change_watched_files`).
+    WatchedFilesDebounced(Vec<PathBuf>),
 }
 
+/// Source paths reported by `workspace/didChangeWatchedFiles` since the last debounced disk
+/// invalidation, and a generation counter used to tell whether another event arrived while a
+/// debounce timer was sleeping (see `did_change_watched_files`).
+#[derive(Default)]
+struct WatchedFileDebounce {
+    pending: HashSet<PathBuf>,
+    generation: u64,
+}
+
+/// How long to wait after the last `workspace/didChangeWatchedFiles` event before coalescing
+/// everything that arrived during the quiet period into a single `invalidate_disk` call,
+/// rather than rechecking once per file (e.g. on a branch switch that touches many files).
+const WATCHED_FILE_DEBOUNCE_PERIOD: Duration = Duration::from_millis(150);
+
 struct Server {
     send: Arc<dyn Fn(Message) + Send + Sync + 'static>,
     immediately_handled_events: Arc<Mutex<Vec<ImmediatelyHandledEvent>>>,
@@ -181,6 +494,146 @@ struct Server {
     site_package_path: Vec<PathBuf>,
     outgoing_request_id: Arc<AtomicI32>,
     outgoing_requests: Mutex<HashMap<RequestId, Request>>,
+    /// Bumped every time the content backing the checked state may have changed (an
+    /// in-memory edit, or a committed recheck), so an in-flight read request can tell
+    /// whether the answer it just computed is still about the content that is live now.
+    content_revision: AtomicU64,
+    /// Requests whose result was discovered to be stale (see `content_revision`), and
+    /// which should be recomputed against fresh content rather than returned.
+    retry_queue: Mutex<Vec<Request>>,
+    /// Where read-only request handlers running on worker threads send their answers
+    /// back to the main loop (see `RequestDispatcher` and `process_worker_read_result`).
+    worker_results: Sender<WorkerReadResult>,
+    /// Bounded pool of threads that run read-only request handlers (see
+    /// `RequestDispatcher::on`).
+    read_request_pool: ReadRequestPool,
+    /// Directory holding the search-path fingerprint (`CacheFingerprint`) for this
+    /// workspace, if enabled (see `Args::cache_dir`/`Args::no_cache`).
+    cache_dir: Option<PathBuf>,
+    /// Set once the fingerprint has been (re)written for the current `search_path`/
+    /// `site_package_path`, so we don't re-serialize it on every subsequent recheck.
+    cache_primed: AtomicBool,
+    /// The workDoneProgress token for a disk-invalidation recheck currently running on a
+    /// background thread (see `validate_with_disk_invalidation`), if one is in flight and the
+    /// client supports work done progress. Closed out once `RecheckFinished` is processed.
+    disk_recheck_progress: Mutex<Option<NumberOrString>>,
+    /// Pending `workspace/didChangeWatchedFiles` paths waiting out their debounce quiet
+    /// period (see `did_change_watched_files`).
+    watched_file_debounce: Arc<Mutex<WatchedFileDebounce>>,
+    /// Requests marked as canceled, either by an explicit `$/cancelRequest` or because a
+    /// newer request for the same document superseded them (see `RequestDispatcher::on`).
+    /// Shared with worker threads so a request can bail out before doing its query; see
+    /// `process_worker_read_result` for where entries are eventually cleaned up.
+    canceled_requests: Arc<Mutex<HashSet<RequestId>>>,
+    /// The request currently answering for each document, so a newer request about the
+    /// same document can supersede whichever one got there first (see `RequestDispatcher::on`).
+    in_flight_requests: Mutex<HashMap<PathBuf, RequestId>>,
+    /// Where to append structured JSON diagnostics after each recheck (see
+    /// `log_diagnostics_json`), if `Args::diagnostic_json_log` was passed.
+    diagnostic_json_log: Option<PathBuf>,
+}
+
+/// The search paths recorded in `fingerprint.json` under `cache_dir`. The search paths
+/// determine how a module resolves; `Server::new` compares the recorded fingerprint with the
+/// current one and logs whether they match, and `Server::prime_cache` overwrites it. The
+/// target `RuntimeMetadata` is not recorded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CacheFingerprint {
+    search_path: Vec<PathBuf>,
+    site_package_path: Vec<PathBuf>,
+}
+
+impl CacheFingerprint {
+    fn path(cache_dir: &Path) -> PathBuf {
+        cache_dir.join("fingerprint.json")
+    }
+
+    /// Whether `cache_dir` already holds a fingerprint matching `self`, i.e. whether
+    /// whatever is cached under it was built from the same search paths we have now.
+    fn matches_existing(&self, cache_dir: &Path) -> bool {
+        std::fs::read_to_string(Self::path(cache_dir))
+            .ok()
+            .and_then(|contents| serde_json::from_str::<CacheFingerprint>(&contents).ok())
+            .is_some_and(|existing| &existing == self)
+    }
+
+    fn write(&self, cache_dir: &Path) {
+        if std::fs::create_dir_all(cache_dir).is_ok()
+            && let Ok(json) = serde_json::to_string_pretty(self)
+        {
+            let _ = std::fs::write(Self::path(cache_dir), json);
+        }
+    }
+}
+
+/// Schema version for `JsonDiagnostic`, bumped whenever a field is added, renamed, or
+/// removed so a consumer tailing `Args::diagnostic_json_log` can detect incompatible changes.
+const DIAGNOSTIC_JSON_SCHEMA_VERSION: u32 = 5;
+
+/// One diagnostic in the machine-readable log written to `Args::diagnostic_json_log`, built
+/// from the same `shown` errors `Config::compute_diagnostics` turns into LSP `Diagnostic`s.
+///
+/// Scope note: this snapshot doesn't carry the error-collection pipeline itself
+/// (`crate::error::error` isn't present as a file here), so there's no `Loads::collect_errors`
+/// to add a `to_json()` onto; this is assembled instead from the per-error accessors this
+/// file already calls (`path`/`source_range`/`msg`/`error_kind`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JsonDiagnostic {
+    module: String,
+    path: PathBuf,
+    range: lsp_types::Range,
+    severity: &'static str,
+    error_kind: String,
+    message: String,
+    /// A chain of cause notes (e.g., for an assignability error, the generic-instantiation
+    /// or union-branch steps that explain *why* one type isn't assignable to another), in
+    /// the order they should be rendered beneath `message`.
+    ///
+    /// `e.msg()` is already the only place this file sees cause information: the
+    /// assignability checker that produces it (under `crate::alt`) renders a multi-line
+    /// message with the top-level explanation on the first line and each cause/step on its
+    /// own line after, so `compute_diagnostics` splits on that rather than leaving `message`
+    /// as one multi-line blob. A future engine change that hands causes over structurally
+    /// instead of pre-rendered into the string would let this split go away.
+    notes: Vec<String>,
+    /// Whether this is a future-incompatibility warning (a check that isn't an error today but
+    /// is slated to become one, e.g. a deprecated construct) rather than a present-day error.
+    ///
+    /// NOT IMPLEMENTED: always absent from the serialized JSON, never `Some(_)`. Distinguishing
+    /// the two needs a `future_breakage` marker on `Error`/`ErrorConfigs`
+    /// (`crate::config::error::ErrorConfigs`), which isn't a file in this tree, so there's
+    /// nothing on the `shown` errors this file sees to read that marker from. `Option` rather
+    /// than a plain `bool` so a consumer can tell "not populated yet" apart from "populated and
+    /// false" once this is implemented, instead of every diagnostic looking like a confirmed
+    /// present-day error.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    future_incompatible: Option<bool>,
+}
+
+/// One line of `Args::diagnostic_json_log`: every diagnostic produced by a single recheck.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JsonDiagnosticsLog {
+    schema_version: u32,
+    diagnostics: Vec<JsonDiagnostic>,
+    /// Diagnostics that were filtered out by config (per-file disables, ignore rules,
+    /// `# type: ignore`-style suppressions, ...) rather than shown, alongside why.
+    ///
+    /// NOT IMPLEMENTED: always absent from the serialized JSON, never `Some(_)`. Reporting
+    /// these needs a parallel `.suppressed` list next to `.shown` on `Loads::collect_errors`
+    /// (`crate::config::error::ErrorConfigs`), which isn't a file in this tree, and
+    /// `Config::compute_diagnostics` only ever reads `.shown` today. `Option` rather than a
+    /// bare `Vec` so an absent key unambiguously means "not computed", not "computed, and
+    /// nothing was suppressed".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suppressed: Option<Vec<SuppressedJsonDiagnostic>>,
+}
+
+/// A diagnostic from `JsonDiagnosticsLog::suppressed`: what was suppressed and why.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SuppressedJsonDiagnostic {
+    diagnostic: JsonDiagnostic,
+    /// e.g. "inline `# type: ignore` comment", "error kind disabled in config", ...
+    suppression_source: String,
 }
 
 /// Temporary "configuration": this is all that is necessary to run an LSP at a given root.
@@ -235,12 +688,16 @@ impl Config {
             .collect::<Vec<_>>()
     }
 
+    /// Returns the LSP diagnostics to publish alongside the same data in `JsonDiagnostic`
+    /// form, for `Server::log_diagnostics_json` to append if `Args::diagnostic_json_log`
+    /// is set.
     fn compute_diagnostics(
         &self,
         transaction: &Transaction,
         handles: Vec<(Handle, Require)>,
-    ) -> SmallMap<PathBuf, Vec<Diagnostic>> {
+    ) -> (SmallMap<PathBuf, Vec<Diagnostic>>, Vec<JsonDiagnostic>) {
         let mut diags: SmallMap<PathBuf, Vec<Diagnostic>> = SmallMap::new();
+        let mut json_diags = Vec::new();
         let open_files = self.open_files.lock();
         for x in open_files.keys() {
             diags.insert(x.as_path().to_owned(), Vec::new());
@@ -253,38 +710,377 @@ impl Config {
         {
             if let Some(path) = to_real_path(e.path()) {
                 if open_files.contains_key(path) {
+                    let range = source_range_to_range(e.source_range());
+                    let error_kind = e.error_kind().to_name().to_owned();
+                    let full_message = e.msg();
+                    let mut lines = full_message.lines();
+                    let message = lines.next().unwrap_or(full_message).to_owned();
+                    let notes = lines.map(|line| line.to_owned()).collect();
+                    json_diags.push(JsonDiagnostic {
+                        module: module_from_path(path, &self.search_path)
+                            .as_str()
+                            .to_owned(),
+                        path: path.to_owned(),
+                        range: range.clone(),
+                        severity: "error",
+                        error_kind: error_kind.clone(),
+                        message,
+                        notes,
+                        future_incompatible: None,
+                    });
                     diags.entry(path.to_owned()).or_default().push(Diagnostic {
-                        range: source_range_to_range(e.source_range()),
+                        range,
                         severity: Some(lsp_types::DiagnosticSeverity::ERROR),
                         source: Some("Pyrefly".to_owned()),
                         message: e.msg().to_owned(),
-                        code: Some(lsp_types::NumberOrString::String(
-                            e.error_kind().to_name().to_owned(),
-                        )),
+                        code: Some(lsp_types::NumberOrString::String(error_kind)),
                         ..Default::default()
                     });
                 }
             }
         }
-        diags
+        (diags, json_diags)
+    }
+}
+
+/// What `Snapshot::completion` stashes in a `CompletionItem`'s `data` field so
+/// `Snapshot::resolve_completion_item` can hand it back on demand instead of sending it to
+/// the client (and the client rendering it) for every item up front.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CompletionItemData {
+    detail: Option<String>,
+    documentation: Option<Documentation>,
+}
+
+/// A cheap-to-clone, `Send + Sync` view of everything a read-only IDE request needs.
+/// Built once per request by `Server::snapshot` and moved onto a worker thread, so the
+/// request can be answered without borrowing from `Server` or blocking the main loop.
+#[derive(Clone)]
+struct Snapshot {
+    state: Arc<State>,
+    configs: Arc<RwLock<SmallMap<PathBuf, Config>>>,
+    default_config: Arc<Config>,
+}
+
+impl Snapshot {
+    fn get_config_with<F, R>(&self, uri: PathBuf, f: F) -> R
+    where
+        F: FnOnce(&Config) -> R,
+    {
+        f(find_config(self.configs.read().iter(), &self.default_config, &uri))
+    }
+
+    fn make_handle(&self, uri: &Url) -> Handle {
+        let path = uri.to_file_path().unwrap();
+        self.get_config_with(path.clone(), |config| {
+            let module = module_from_path(&path, &config.search_path);
+            let module_path = if config.open_files.lock().contains_key(&path) {
+                ModulePath::memory(path)
+            } else {
+                ModulePath::filesystem(path)
+            };
+            Handle::new(
+                module,
+                module_path,
+                config.runtime_metadata.dupe(),
+                config.loader.dupe(),
+            )
+        })
+    }
+
+    fn goto_definition(
+        &self,
+        transaction: &Transaction<'_>,
+        params: GotoDefinitionParams,
+    ) -> Option<GotoDefinitionResponse> {
+        let uri = &params.text_document_position_params.text_document.uri;
+        if self.get_config_with(uri.to_file_path().unwrap(), |config| {
+            config.disable_language_services
+        }) {
+            return None;
+        }
+        let handle = self.make_handle(uri);
+        let info = transaction.get_module_info(&handle)?;
+        let range = position_to_text_size(&info, params.text_document_position_params.position);
+        let TextRangeWithModuleInfo {
+            module_info: definition_module_info,
+            range,
+        } = transaction.goto_definition(&handle, range)?;
+        let path = to_real_path(definition_module_info.path())?;
+        let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+        Some(GotoDefinitionResponse::Scalar(Location {
+            uri: Url::from_file_path(path).unwrap(),
+            range: source_range_to_range(&definition_module_info.source_range(range)),
+        }))
+    }
+
+    fn completion(
+        &self,
+        transaction: &Transaction<'_>,
+        params: CompletionParams,
+    ) -> anyhow::Result<CompletionResponse> {
+        let uri = &params.text_document_position.text_document.uri;
+        if self.get_config_with(uri.to_file_path().unwrap(), |config| {
+            config.disable_language_services
+        }) {
+            return Ok(CompletionResponse::List(CompletionList {
+                is_incomplete: false,
+                items: Vec::new(),
+            }));
+        }
+        let handle = self.make_handle(uri);
+        let mut items = transaction
+            .get_module_info(&handle)
+            .map(|info| {
+                transaction.completion(
+                    &handle,
+                    position_to_text_size(&info, params.text_document_position.position),
+                )
+            })
+            .unwrap_or_default();
+        // `detail`/`documentation` can be a full rendered type signature, which is wasted
+        // work and wire bytes for the vast majority of a large completion list the user
+        // never looks at. Strip it out of the initial response and stash it for
+        // `resolve_completion_item` to hand back only for the one item the user highlights.
+        for item in &mut items {
+            if item.detail.is_some() || item.documentation.is_some() {
+                let data = CompletionItemData {
+                    detail: item.detail.take(),
+                    documentation: item.documentation.take(),
+                };
+                item.data = Some(serde_json::to_value(&data).unwrap());
+            }
+        }
+        Ok(CompletionResponse::List(CompletionList {
+            is_incomplete: false,
+            items,
+        }))
+    }
+
+    /// Hand back the `detail`/`documentation` that `completion` stashed in the item's `data`
+    /// rather than sending upfront (see `completion`). `Transaction::completion` computes
+    /// every item's signature in the same call that produces the list, so this can't redo
+    /// that work any more cheaply than `completion` already did; the win is purely in not
+    /// serializing and rendering it for items the user never resolves.
+    fn resolve_completion_item(&self, mut item: CompletionItem) -> CompletionItem {
+        let Some(data) = item
+            .data
+            .take()
+            .and_then(|data| serde_json::from_value::<CompletionItemData>(data).ok())
+        else {
+            return item;
+        };
+        item.detail = data.detail;
+        item.documentation = data.documentation;
+        item
+    }
+
+    fn hover(&self, transaction: &Transaction<'_>, params: HoverParams) -> Option<Hover> {
+        let uri = &params.text_document_position_params.text_document.uri;
+        if self.get_config_with(uri.to_file_path().unwrap(), |config| {
+            config.disable_language_services
+        }) {
+            return None;
+        }
+        let handle = self.make_handle(uri);
+        let info = transaction.get_module_info(&handle)?;
+        let range = position_to_text_size(&info, params.text_document_position_params.position);
+        let t = transaction.hover(&handle, range)?;
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!(
+                    r#"```python
+{}
+```"#,
+                    t
+                ),
+            }),
+            range: None,
+        })
+    }
+
+    fn inlay_hints(
+        &self,
+        transaction: &Transaction<'_>,
+        params: InlayHintParams,
+    ) -> Option<Vec<InlayHint>> {
+        let uri = &params.text_document.uri;
+        if self.get_config_with(uri.to_file_path().unwrap(), |config| {
+            config.disable_language_services
+        }) {
+            return None;
+        }
+        let handle = self.make_handle(uri);
+        let info = transaction.get_module_info(&handle)?;
+        let t = transaction.inlay_hints(&handle)?;
+        Some(t.into_map(|x| {
+            let position = text_size_to_position(&info, x.0);
+            InlayHint {
+                position,
+                label: InlayHintLabel::String(x.1.clone()),
+                kind: None,
+                text_edits: Some(vec![TextEdit {
+                    range: Range::new(position, position),
+                    new_text: x.1,
+                }]),
+                tooltip: None,
+                padding_left: None,
+                padding_right: None,
+                data: None,
+            }
+        }))
+    }
+
+    /// Quick fixes for the diagnostics `params.context.diagnostics` overlapping
+    /// `params.range`: insert a suggested import for an unresolved name, offer to suppress
+    /// the specific error code on the offending line, and (where we already have an inlay
+    /// hint for the spot) fill in the inferred annotation.
+    fn code_action(
+        &self,
+        transaction: &Transaction<'_>,
+        params: CodeActionParams,
+    ) -> Option<Vec<CodeActionOrCommand>> {
+        let uri = &params.text_document.uri;
+        if self.get_config_with(uri.to_file_path().unwrap(), |config| {
+            config.disable_language_services
+        }) {
+            return None;
+        }
+        let handle = self.make_handle(uri);
+        let info = transaction.get_module_info(&handle)?;
+        let inferred_annotations: Vec<(lsp_types::Position, String)> = transaction
+            .inlay_hints(&handle)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(offset, label)| (text_size_to_position(&info, offset), label))
+            .collect();
+
+        let mut actions = Vec::new();
+        for diagnostic in &params.context.diagnostics {
+            if let Some(module) = suggested_import_from_diagnostic(&diagnostic.message) {
+                actions.push(CodeActionOrCommand::CodeAction(missing_import_action(
+                    uri, module, diagnostic,
+                )));
+            }
+            if let Some(code) = &diagnostic.code {
+                actions.push(CodeActionOrCommand::CodeAction(suppress_diagnostic_action(
+                    uri, code, diagnostic,
+                )));
+            }
+            for (position, annotation) in &inferred_annotations {
+                if position_within_range(*position, diagnostic.range) {
+                    actions.push(CodeActionOrCommand::CodeAction(annotate_type_action(
+                        uri, *position, annotation, diagnostic,
+                    )));
+                }
+            }
+        }
+        Some(actions)
+    }
+
+    /// The text backing `uri` as it stands right now: the in-memory buffer if it's open,
+    /// otherwise whatever is on disk.
+    fn document_text(&self, uri: &Url) -> Option<Arc<String>> {
+        let path = uri.to_file_path().ok()?;
+        if let Some(text) = self.get_config_with(path.clone(), |config| {
+            config.open_files.lock().get(&path).cloned()
+        }) {
+            return Some(text);
+        }
+        std::fs::read_to_string(&path).ok().map(Arc::new)
     }
+
+    fn document_symbol(&self, params: DocumentSymbolParams) -> Option<DocumentSymbolResponse> {
+        let uri = &params.text_document.uri;
+        if self.get_config_with(uri.to_file_path().unwrap(), |config| {
+            config.disable_language_services
+        }) {
+            return None;
+        }
+        let text = self.document_text(uri)?;
+        Some(DocumentSymbolResponse::Nested(scan_python_symbols(&text)))
+    }
+
+    fn workspace_symbol(&self, params: WorkspaceSymbolParams) -> Option<Vec<SymbolInformation>> {
+        let query = params.query.to_lowercase();
+        let mut results = Vec::new();
+        let configs = self.configs.read();
+        for config in configs.values().chain(once(self.default_config.as_ref())) {
+            if config.disable_language_services {
+                continue;
+            }
+            for path in config.open_files.lock().keys() {
+                let Ok(uri) = Url::from_file_path(path) else {
+                    continue;
+                };
+                let Some(text) = self.document_text(&uri) else {
+                    continue;
+                };
+                collect_matching_symbols(&scan_python_symbols(&text), &uri, &query, &mut results);
+            }
+        }
+        Some(results)
+    }
+}
+
+/// The outcome of a read-only IDE request computed on a background worker thread,
+/// delivered back to the main loop over `Server::worker_results`.
+struct WorkerReadResult {
+    id: RequestId,
+    /// The original request, kept around in case the result turns out to be stale and
+    /// needs to be recomputed (see `Server::content_revision`).
+    request: Request,
+    /// The `Server::content_revision` that was live when this job was dispatched.
+    revision_at_start: u64,
+    /// The document this request was about, if one could be extracted from its params (see
+    /// `document_uri_from_params`), so the in-flight-request entry it may have claimed in
+    /// `Server::in_flight_requests` can be released once it's done.
+    document: Option<PathBuf>,
+    response: Response,
 }
 
+/// On Windows, background threads run at a lower scheduling priority than whichever
+/// thread currently has window focus; since we're a console-less language server, that
+/// demotion instead falls on us arbitrarily, and on low-core machines the worker threads
+/// dispatching read-only requests can starve the main loop that needs to keep reading
+/// `DidChangeTextDocument`/`$/cancelRequest`. Boost the main loop thread back up, the
+/// same workaround rust-analyzer applies for the same reason.
+#[cfg(windows)]
+fn boost_main_thread_priority() {
+    use windows_sys::Win32::System::Threading::GetCurrentThread;
+    use windows_sys::Win32::System::Threading::SetThreadPriority;
+    use windows_sys::Win32::System::Threading::THREAD_PRIORITY_ABOVE_NORMAL;
+    unsafe {
+        SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_ABOVE_NORMAL);
+    }
+}
+
+#[cfg(not(windows))]
+fn boost_main_thread_priority() {}
+
 pub fn run_lsp(
     connection: Arc<Connection>,
     wait_on_connection: impl FnOnce() -> anyhow::Result<()> + Send + 'static,
     args: Args,
 ) -> anyhow::Result<CommandExitStatus> {
+    boost_main_thread_priority();
     // Run the server and wait for the two threads to end (typically by trigger LSP Exit event).
     let server_capabilities = serde_json::to_value(&ServerCapabilities {
-        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(
+            TextDocumentSyncKind::INCREMENTAL,
+        )),
         definition_provider: Some(OneOf::Left(true)),
         completion_provider: Some(CompletionOptions {
             trigger_characters: Some(vec![".".to_owned()]),
+            resolve_provider: Some(true),
             ..Default::default()
         }),
         hover_provider: Some(HoverProviderCapability::Simple(true)),
         inlay_hint_provider: Some(OneOf::Left(true)),
+        code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        workspace_symbol_provider: Some(OneOf::Left(true)),
         ..Default::default()
     })
     .unwrap();
@@ -300,25 +1096,51 @@ pub fn run_lsp(
     };
     let search_path = args.search_path;
     let site_package_path = args.site_package_path;
+    let cache_dir = if args.no_cache { None } else { args.cache_dir };
+    let diagnostic_json_log = args.diagnostic_json_log;
     let connection_for_send = connection.dupe();
     let send = move |msg| connection_for_send.sender.send(msg).unwrap();
+    // Read-only requests are answered on worker threads (see `RequestDispatcher`) so a
+    // slow `completion` or `inlay_hints` call can't block the receiver loop from seeing
+    // `DidChangeTextDocument`/`$/cancelRequest` in the meantime; their answers come back
+    // to the main loop over this channel.
+    let (worker_results_tx, worker_results_rx) = unbounded();
     let server = Server::new(
         Arc::new(send),
         initialization_params,
         search_path,
         site_package_path,
+        cache_dir,
+        worker_results_tx,
+        diagnostic_json_log,
     );
+    server.register_watched_files();
     eprintln!("Reading messages");
     let mut ide_transaction_manager = IDETransactionManager::default();
-    let mut canceled_requests = HashSet::new();
-    for msg in &connection.receiver {
-        if matches!(&msg, Message::Request(req) if connection.handle_shutdown(req)?) {
-            break;
-        }
-        server.process_lsp_message(&mut ide_transaction_manager, &mut canceled_requests, msg)?;
-        let immediately_handled_events = mem::take(&mut *server.immediately_handled_events.lock());
-        for msg in immediately_handled_events {
-            server.process_immediately_handled_event(&mut ide_transaction_manager, msg)?;
+    loop {
+        select! {
+            recv(connection.receiver) -> msg => {
+                let msg = match msg {
+                    Ok(msg) => msg,
+                    Err(_) => break,
+                };
+                if matches!(&msg, Message::Request(req) if connection.handle_shutdown(req)?) {
+                    break;
+                }
+                server.process_lsp_message(&mut ide_transaction_manager, msg)?;
+                let immediately_handled_events = mem::take(&mut *server.immediately_handled_events.lock());
+                for msg in immediately_handled_events {
+                    server.process_immediately_handled_event(&mut ide_transaction_manager, msg)?;
+                }
+                server.drain_retry_queue(&mut ide_transaction_manager)?;
+            }
+            recv(worker_results_rx) -> result => {
+                // `server` (which owns the paired `Sender`) outlives this loop, so the
+                // channel can only disconnect once we've already broken out above.
+                let result = result.expect("worker_results_tx is held by server");
+                server.process_worker_read_result(result);
+                server.drain_retry_queue(&mut ide_transaction_manager)?;
+            }
         }
     }
     wait_on_connection()?;
@@ -346,6 +1168,28 @@ impl Args {
     }
 }
 
+/// Finds a config for a file path: longest config which is a prefix of the file wins.
+fn find_config<'a>(
+    configs: Iter<'a, PathBuf, Config>,
+    default: &'a Config,
+    uri: &Path,
+) -> &'a Config {
+    configs
+        .filter(|(key, _)| uri.starts_with(key))
+        .max_by(|(key1, _), (key2, _)| key2.ancestors().count().cmp(&key1.ancestors().count()))
+        .map_or(default, |(_, config)| config)
+}
+
+/// Whether `path` names a pyrefly/pyproject config file rather than a Python source, so a
+/// `workspace/didChangeWatchedFiles` event for it should refresh settings instead of just
+/// invalidating a module (see `Server::did_change_watched_files`).
+fn is_config_file(path: &Path) -> bool {
+    matches!(
+        path.file_name().and_then(|name| name.to_str()),
+        Some("pyrefly.toml") | Some("pyproject.toml")
+    )
+}
+
 /// Convert to a path we can show to the user. The contents may not match the disk, but it has
 /// to be basically right.
 fn to_real_path(path: &ModulePath) -> Option<&Path> {
@@ -389,8 +1233,13 @@ impl Server {
     ) -> anyhow::Result<()> {
         match msg {
             ImmediatelyHandledEvent::RecheckFinished => {
+                self.end_recheck_progress(self.disk_recheck_progress.lock().take());
+                self.bump_content_revision();
                 self.validate_in_memory(ide_transaction_manager)?;
             }
+            ImmediatelyHandledEvent::WatchedFilesDebounced(paths) => {
+                self.validate_with_disk_invalidation(paths)?;
+            }
         }
         Ok(())
     }
@@ -398,12 +1247,11 @@ impl Server {
     fn process_lsp_message<'a>(
         &'a self,
         ide_transaction_manager: &mut IDETransactionManager<'a>,
-        canceled_requests: &mut HashSet<RequestId>,
         msg: Message,
     ) -> anyhow::Result<()> {
         match msg {
             Message::Request(x) => {
-                if canceled_requests.remove(&x.id) {
+                if self.canceled_requests.lock().remove(&x.id) {
                     let message = format!("Request {} is canceled", x.id);
                     eprintln!("{message}");
                     self.send_response(Response::new_err(
@@ -414,45 +1262,39 @@ impl Server {
                     return Ok(());
                 }
                 eprintln!("Handling non-canceled request ({})", x.id);
-                if let Some(params) = as_request::<GotoDefinition>(&x) {
-                    let default_response = GotoDefinitionResponse::Array(Vec::new());
-                    let transaction =
-                        ide_transaction_manager.non_commitable_transaction(&self.state);
-                    self.send_response(new_response(
-                        x.id,
-                        Ok(self
-                            .goto_definition(&transaction, params)
-                            .unwrap_or(default_response)),
-                    ));
-                    ide_transaction_manager.save(transaction);
-                } else if let Some(params) = as_request::<Completion>(&x) {
-                    let transaction =
-                        ide_transaction_manager.non_commitable_transaction(&self.state);
-                    self.send_response(new_response(x.id, self.completion(&transaction, params)));
-                    ide_transaction_manager.save(transaction);
-                } else if let Some(params) = as_request::<HoverRequest>(&x) {
-                    let default_response = Hover {
-                        contents: HoverContents::Array(Vec::new()),
-                        range: None,
-                    };
-                    let transaction =
-                        ide_transaction_manager.non_commitable_transaction(&self.state);
-                    self.send_response(new_response(
-                        x.id,
-                        Ok(self.hover(&transaction, params).unwrap_or(default_response)),
-                    ));
-                    ide_transaction_manager.save(transaction);
-                } else if let Some(params) = as_request::<InlayHintRequest>(&x) {
-                    let transaction =
-                        ide_transaction_manager.non_commitable_transaction(&self.state);
-                    self.send_response(new_response(
-                        x.id,
-                        Ok(self.inlay_hints(&transaction, params).unwrap_or_default()),
-                    ));
-                    ide_transaction_manager.save(transaction);
-                } else {
-                    eprintln!("Unhandled request: {x:?}");
-                }
+                RequestDispatcher::new(self, x)
+                    .on::<GotoDefinition, _>(|snapshot, transaction, params| {
+                        let default_response = GotoDefinitionResponse::Array(Vec::new());
+                        Ok(snapshot
+                            .goto_definition(transaction, params)
+                            .unwrap_or(default_response))
+                    })
+                    .on::<Completion, _>(|snapshot, transaction, params| {
+                        snapshot.completion(transaction, params)
+                    })
+                    .on::<ResolveCompletionItem, _>(|snapshot, _transaction, params| {
+                        Ok(snapshot.resolve_completion_item(params))
+                    })
+                    .on::<HoverRequest, _>(|snapshot, transaction, params| {
+                        let default_response = Hover {
+                            contents: HoverContents::Array(Vec::new()),
+                            range: None,
+                        };
+                        Ok(snapshot.hover(transaction, params).unwrap_or(default_response))
+                    })
+                    .on::<InlayHintRequest, _>(|snapshot, transaction, params| {
+                        Ok(snapshot.inlay_hints(transaction, params).unwrap_or_default())
+                    })
+                    .on::<CodeActionRequest, _>(|snapshot, transaction, params| {
+                        Ok(snapshot.code_action(transaction, params))
+                    })
+                    .on::<DocumentSymbolRequest, _>(|snapshot, _transaction, params| {
+                        Ok(snapshot.document_symbol(params))
+                    })
+                    .on::<WorkspaceSymbolRequest, _>(|snapshot, _transaction, params| {
+                        Ok(snapshot.workspace_symbol(params))
+                    })
+                    .finish();
                 Ok(())
             }
             Message::Response(x) => {
@@ -463,52 +1305,47 @@ impl Server {
                     Ok(())
                 }
             }
-            Message::Notification(x) => {
-                if let Some(params) = as_notification::<DidOpenTextDocument>(&x) {
-                    self.did_open(ide_transaction_manager, params)
-                } else if let Some(params) = as_notification::<DidChangeTextDocument>(&x) {
-                    self.did_change(ide_transaction_manager, params)
-                } else if let Some(params) = as_notification::<DidCloseTextDocument>(&x) {
-                    self.did_close(params)
-                } else if let Some(params) = as_notification::<DidSaveTextDocument>(&x) {
-                    self.did_save(params)
-                } else if let Some(params) = as_notification::<Cancel>(&x) {
+            Message::Notification(x) => NotificationDispatcher::new(self, ide_transaction_manager, x)
+                .on::<DidOpenTextDocument>(|server, manager, params| server.did_open(manager, params))
+                .on::<DidChangeTextDocument>(|server, manager, params| server.did_change(manager, params))
+                .on::<DidCloseTextDocument>(|server, _manager, params| server.did_close(params))
+                .on::<DidSaveTextDocument>(|server, _manager, params| server.did_save(params))
+                .on::<DidChangeWatchedFiles>(|server, _manager, params| {
+                    server.did_change_watched_files(params)
+                })
+                .on::<Cancel>(|server, _manager, params| {
                     let id = match params.id {
                         NumberOrString::Number(i) => RequestId::from(i),
                         NumberOrString::String(s) => RequestId::from(s),
                     };
-                    canceled_requests.insert(id);
-                    Ok(())
-                } else if as_notification::<DidChangeConfiguration>(&x).is_some() {
-                    self.change_configuration();
+                    server.canceled_requests.lock().insert(id);
                     Ok(())
-                } else {
-                    eprintln!("Unhandled notification: {x:?}");
+                })
+                .on::<DidChangeConfiguration>(|server, _manager, _params| {
+                    server.change_configuration();
                     Ok(())
-                }
-            }
+                })
+                .finish(),
         }
     }
 
-    /// Finds a config for a file path: longest config which is a prefix of the file wins
-    fn get_config<'a>(
-        &self,
-        configs: Iter<'a, PathBuf, Config>,
-        default: &'a Config,
-        uri: &Path,
-    ) -> &'a Config {
-        configs
-            .filter(|(key, _)| uri.starts_with(key))
-            .max_by(|(key1, _), (key2, _)| key2.ancestors().count().cmp(&key1.ancestors().count()))
-            .map_or(default, |(_, config)| config)
-    }
-
     /// TODO(connernilsen): replace with real config logic
     fn get_config_with<F, R>(&self, uri: PathBuf, f: F) -> R
     where
         F: FnOnce(&Config) -> R,
     {
-        f(self.get_config(self.configs.read().iter(), &self.default_config, &uri))
+        f(find_config(self.configs.read().iter(), &self.default_config, &uri))
+    }
+
+    /// A cheap-to-clone, `Send + Sync` view of the server's current state, handed to
+    /// background worker threads so read-only requests don't have to be computed on the
+    /// main loop thread.
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            state: self.state.dupe(),
+            configs: self.configs.dupe(),
+            default_config: self.default_config.dupe(),
+        }
     }
 
     fn new(
@@ -516,8 +1353,12 @@ impl Server {
         initialize_params: InitializeParams,
         search_path: Vec<PathBuf>,
         site_package_path: Vec<PathBuf>,
+        cache_dir: Option<PathBuf>,
+        worker_results: Sender<WorkerReadResult>,
+        diagnostic_json_log: Option<PathBuf>,
     ) -> Self {
-        let folders = if let Some(capability) = &initialize_params.capabilities.workspace
+        let folders: Vec<PathBuf> = if let Some(capability) =
+            &initialize_params.capabilities.workspace
             && let Some(true) = capability.workspace_folders
             && let Some(folders) = &initialize_params.workspace_folders
         {
@@ -528,6 +1369,30 @@ impl Server {
         } else {
             Vec::new()
         };
+        let cache_dir = cache_dir.or_else(|| Some(folders.first()?.join(".pyrefly_cache")));
+        let fingerprint = CacheFingerprint {
+            search_path: search_path.clone(),
+            site_package_path: site_package_path.clone(),
+        };
+        // NOT IMPLEMENTED: a real content/interface-fingerprint cache (persisting each
+        // `Handle`'s `Solutions` so a fingerprint match can skip its recheck) needs
+        // `Solutions`, per-handle `Transaction` storage, and the dependency graph, none of
+        // which are files in this tree. `State::new` only takes an optional `ConfigFinder`
+        // and has no hook to seed it from such a cache, so today a fingerprint match only
+        // tells us the search paths are unchanged - it can't skip any recheck work.
+        if let Some(cache_dir) = &cache_dir {
+            if fingerprint.matches_existing(cache_dir) {
+                eprintln!(
+                    "Found matching search-path fingerprint at {} (does not skip the recheck)",
+                    cache_dir.display()
+                );
+            } else {
+                eprintln!(
+                    "No matching fingerprint at {} (missing or stale), will (re)write it",
+                    cache_dir.display()
+                );
+            }
+        }
 
         let mut s = Self {
             send,
@@ -545,16 +1410,107 @@ impl Server {
             site_package_path,
             outgoing_request_id: Arc::new(AtomicI32::new(1)),
             outgoing_requests: Mutex::new(HashMap::new()),
+            content_revision: AtomicU64::new(0),
+            retry_queue: Mutex::new(Vec::new()),
+            worker_results,
+            read_request_pool: ReadRequestPool::new(
+                std::thread::available_parallelism().map_or(4, |n| n.get()),
+            ),
+            cache_dir,
+            cache_primed: AtomicBool::new(false),
+            disk_recheck_progress: Mutex::new(None),
+            watched_file_debounce: Arc::new(Mutex::new(WatchedFileDebounce::default())),
+            canceled_requests: Arc::new(Mutex::new(HashSet::new())),
+            in_flight_requests: Mutex::new(HashMap::new()),
+            diagnostic_json_log,
         };
         s.configure(folders);
 
         s
     }
 
+    /// Write (or refresh) the on-disk cache fingerprint for the current search paths, once
+    /// per process. Cheap to call after every recheck; does nothing once it has already run.
+    fn prime_cache(&self) {
+        if let Some(cache_dir) = &self.cache_dir
+            && !self.cache_primed.swap(true, Ordering::SeqCst)
+        {
+            CacheFingerprint {
+                search_path: self.search_path.clone(),
+                site_package_path: self.site_package_path.clone(),
+            }
+            .write(cache_dir);
+        }
+    }
+
     fn send_response(&self, x: Response) {
         (self.send)(Message::Response(x))
     }
 
+    fn content_revision(&self) -> u64 {
+        self.content_revision.load(Ordering::SeqCst)
+    }
+
+    /// Record that the content backing the checked state may have changed, invalidating
+    /// any read request whose answer was computed against an earlier revision.
+    fn bump_content_revision(&self) {
+        self.content_revision.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Drain requests that were found to be stale (see `content_revision`) and
+    /// reprocess them now that the triggering change has been applied.
+    fn drain_retry_queue<'a>(
+        &'a self,
+        ide_transaction_manager: &mut IDETransactionManager<'a>,
+    ) -> anyhow::Result<()> {
+        for request in mem::take(&mut *self.retry_queue.lock()) {
+            self.process_lsp_message(ide_transaction_manager, Message::Request(request))?;
+        }
+        Ok(())
+    }
+
+    /// Release `document`'s entry in `in_flight_requests` once `id` is done with it, as long
+    /// as a newer request for the same document hasn't already claimed it.
+    fn finish_in_flight_request(&self, document: &Option<PathBuf>, id: &RequestId) {
+        if let Some(document) = document {
+            let mut in_flight = self.in_flight_requests.lock();
+            if in_flight.get(document) == Some(id) {
+                in_flight.remove(document);
+            }
+        }
+    }
+
+    /// Handle a read-only request's answer coming back from a worker thread. If it was
+    /// canceled (explicitly, or superseded by a newer request for the same document — see
+    /// `RequestDispatcher::on`), report that instead of trusting the computed response, even
+    /// if the worker ran to completion before noticing. Otherwise, if the content it was
+    /// computed against is still current, send it; if not, queue it for retry against the
+    /// content that is live now.
+    ///
+    /// NOT A TRUE INTERRUPT: `content_revision` is captured when the request is dispatched
+    /// (`RequestDispatcher::on`) and compared only here, after the handler finishes. A handler
+    /// already running against input that goes stale mid-computation (e.g. a big `hover`/`completion` query
+    /// racing a fast edit) still runs to completion on the stale snapshot before this notices
+    /// and retries it — `Transaction` doesn't expose a checkpoint to poll, so there's no point
+    /// to interrupt it at. This bounds correctness (a stale answer is never returned to the
+    /// client) but not latency (the wasted CPU from the stale run isn't reclaimed).
+    fn process_worker_read_result(&self, result: WorkerReadResult) {
+        self.finish_in_flight_request(&result.document, &result.id);
+        if self.canceled_requests.lock().remove(&result.id) {
+            eprintln!("Request {} was canceled, discarding its result", result.id);
+            self.send_response(Response::new_err(
+                result.id,
+                ErrorCode::RequestCanceled as i32,
+                "request is canceled".to_owned(),
+            ));
+        } else if self.content_revision() != result.revision_at_start {
+            eprintln!("Stale request {}, retrying against fresh content", result.id);
+            self.retry_queue.lock().push(result.request);
+        } else {
+            self.send_response(result.response);
+        }
+    }
+
     fn send_request<T>(&self, params: T::Params)
     where
         T: lsp_types::request::Request,
@@ -569,6 +1525,59 @@ impl Server {
         self.outgoing_requests.lock().insert(id, request);
     }
 
+    fn send_notification<T>(&self, params: T::Params)
+    where
+        T: lsp_types::notification::Notification,
+    {
+        (self.send)(Message::Notification(new_notification::<T>(params)));
+    }
+
+    fn client_supports_work_done_progress(&self) -> bool {
+        self.initialize_params
+            .capabilities
+            .window
+            .as_ref()
+            .and_then(|window| window.work_done_progress)
+            .unwrap_or(false)
+    }
+
+    /// Tells the client we're about to start a (potentially long) recheck, so it can show
+    /// "Pyrefly: checking files" (with a "0/N" message) instead of leaving the user staring
+    /// at a frozen editor. Only the begin and end notifications are sent, no reports.
+    /// Returns the progress token to pass to `end_recheck_progress` once the recheck is done,
+    /// or `None` if the client didn't advertise `window.workDoneProgress` or there's nothing
+    /// to check.
+    fn begin_recheck_progress(&self, file_count: usize) -> Option<NumberOrString> {
+        if file_count == 0 || !self.client_supports_work_done_progress() {
+            return None;
+        }
+        let token = NumberOrString::Number(self.outgoing_request_id.fetch_add(1, Ordering::SeqCst));
+        self.send_request::<WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
+            token: token.clone(),
+        });
+        self.send_notification::<Progress>(ProgressParams {
+            token: token.clone(),
+            value: ProgressParamsValue::WorkDone(WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: "Pyrefly: checking files".to_owned(),
+                cancellable: Some(false),
+                message: Some(format!("0/{file_count}")),
+                percentage: Some(0),
+            })),
+        });
+        Some(token)
+    }
+
+    fn end_recheck_progress(&self, token: Option<NumberOrString>) {
+        if let Some(token) = token {
+            self.send_notification::<Progress>(ProgressParams {
+                token,
+                value: ProgressParamsValue::WorkDone(WorkDoneProgress::End(WorkDoneProgressEnd {
+                    message: None,
+                })),
+            });
+        }
+    }
+
     fn publish_diagnostics_for_uri(&self, uri: Url, diags: Vec<Diagnostic>, version: Option<i32>) {
         publish_diagnostics_for_uri(&self.send, uri, diags, version);
     }
@@ -577,6 +1586,25 @@ impl Server {
         publish_diagnostics(self.send.dupe(), diags);
     }
 
+    /// Appends `diagnostics` to `Args::diagnostic_json_log` as one `JsonDiagnosticsLog` line,
+    /// if that flag was passed. No-op otherwise.
+    fn log_diagnostics_json(&self, diagnostics: Vec<JsonDiagnostic>) {
+        let Some(path) = &self.diagnostic_json_log else {
+            return;
+        };
+        let log = JsonDiagnosticsLog {
+            schema_version: DIAGNOSTIC_JSON_SCHEMA_VERSION,
+            diagnostics,
+            suppressed: None,
+        };
+        let Ok(json) = serde_json::to_string(&log) else {
+            return;
+        };
+        if let Ok(mut file) = std::fs::OpenOptions::new().create(true).append(true).open(path) {
+            let _ = writeln!(file, "{json}");
+        }
+    }
+
     fn validate_in_memory<'a>(
         &'a self,
         ide_transaction_manager: &mut IDETransactionManager<'a>,
@@ -606,15 +1634,27 @@ impl Server {
                 all_handles.extend(handles.clone());
                 config_with_handles.push((config, handles));
             });
+        // NOT IMPLEMENTED: `all_handles` is every open document, every time - this asks
+        // `Transaction::run` to requery all of them on each recheck, relying entirely on
+        // whatever memoization `State`/`Transaction` already does internally, rather than a
+        // demand-driven red/green recompute that could skip a dirty module nothing currently
+        // imports. That demand graph and per-node fingerprinting would live in
+        // `State`/`Transaction` (`crate::state::state`), which isn't a file in this tree, so
+        // there's nothing here in `lsp.rs` to narrow `all_handles` against.
+        let progress_token = self.begin_recheck_progress(all_handles.len());
         transaction.run(&all_handles);
+        self.end_recheck_progress(progress_token);
         match possibly_committable_transaction {
             Ok(transaction) => {
                 self.state.commit_transaction(transaction);
+                self.prime_cache();
                 // In the case where we can commit transactions, `State` already has latest updates.
                 // Therefore, we can compute errors from transactions freshly created from `State``.
                 let transaction = self.state.transaction();
                 for (config, handles) in config_with_handles {
-                    self.publish_diagnostics(config.compute_diagnostics(&transaction, handles));
+                    let (diags, json_diags) = config.compute_diagnostics(&transaction, handles);
+                    self.publish_diagnostics(diags);
+                    self.log_diagnostics_json(json_diags);
                 }
             }
             Err(transaction) => {
@@ -623,7 +1663,9 @@ impl Server {
                 // from the transactions that won't be committed. It will still contain all the
                 // up-to-date in-memory content, but can have stale main `State` content.
                 for (config, handles) in config_with_handles {
-                    self.publish_diagnostics(config.compute_diagnostics(&transaction, handles));
+                    let (diags, json_diags) = config.compute_diagnostics(&transaction, handles);
+                    self.publish_diagnostics(diags);
+                    self.log_diagnostics_json(json_diags);
                 }
                 ide_transaction_manager.save(transaction);
             }
@@ -632,6 +1674,12 @@ impl Server {
     }
 
     fn validate_with_disk_invalidation(&self, invalidate_disk: Vec<PathBuf>) -> anyhow::Result<()> {
+        // Let the client know we're invalidating these files on disk and starting a recheck,
+        // so it doesn't look like the server went silent until `RecheckFinished` arrives and
+        // `validate_in_memory` reports its own progress. The token is closed out in
+        // `process_immediately_handled_event` once the background thread below is done.
+        let progress_token = self.begin_recheck_progress(invalidate_disk.len());
+        *self.disk_recheck_progress.lock() = progress_token;
         let state = self.state.dupe();
         let immediately_handled_events = self.immediately_handled_events.dupe();
         std::thread::spawn(move || {
@@ -653,6 +1701,100 @@ impl Server {
         self.validate_with_disk_invalidation(vec![uri])
     }
 
+    /// Handle external changes to Python sources, config files, and site-package contents
+    /// (a `git checkout`, a formatter run, a `pip install`, ...) that we'd otherwise never
+    /// hear about since `did_save` only fires for files this client has open. Config file
+    /// changes refresh settings right away; source changes are coalesced into a single
+    /// `validate_with_disk_invalidation` once no new event has arrived for
+    /// `WATCHED_FILE_DEBOUNCE_PERIOD`, so a branch switch touching hundreds of files doesn't
+    /// trigger hundreds of rechecks.
+    fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) -> anyhow::Result<()> {
+        let mut config_changed = false;
+        let mut debounce = self.watched_file_debounce.lock();
+        for change in params.changes {
+            if let Ok(path) = change.uri.to_file_path() {
+                if is_config_file(&path) {
+                    config_changed = true;
+                } else {
+                    debounce.pending.insert(path);
+                }
+            }
+        }
+        if debounce.pending.is_empty() {
+            drop(debounce);
+            if config_changed {
+                self.change_configuration();
+            }
+            return Ok(());
+        }
+        debounce.generation += 1;
+        let generation = debounce.generation;
+        drop(debounce);
+
+        let immediately_handled_events = self.immediately_handled_events.dupe();
+        let watched_file_debounce = self.watched_file_debounce.dupe();
+        std::thread::spawn(move || {
+            std::thread::sleep(WATCHED_FILE_DEBOUNCE_PERIOD);
+            let mut debounce = watched_file_debounce.lock();
+            if debounce.generation != generation {
+                // Another event arrived during the quiet period; that event's own timer will
+                // fire the coalesced invalidation instead.
+                return;
+            }
+            let paths = mem::take(&mut debounce.pending).into_iter().collect();
+            drop(debounce);
+            immediately_handled_events
+                .lock()
+                .push(ImmediatelyHandledEvent::WatchedFilesDebounced(paths));
+        });
+
+        if config_changed {
+            self.change_configuration();
+        }
+        Ok(())
+    }
+
+    /// Ask the client to notify us about changes to Python sources, config files, and
+    /// site-package contents via dynamic `workspace/didChangeWatchedFiles` registration, so
+    /// `did_change_watched_files` actually gets called. No-ops if the client didn't advertise
+    /// `workspace.didChangeWatchedFiles.dynamicRegistration`.
+    fn register_watched_files(&self) {
+        let supports_dynamic_registration = self
+            .initialize_params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.did_change_watched_files.as_ref())
+            .and_then(|capability| capability.dynamic_registration)
+            .unwrap_or(false);
+        if !supports_dynamic_registration {
+            return;
+        }
+        let watchers = [
+            "**/*.py",
+            "**/*.pyi",
+            "**/pyrefly.toml",
+            "**/pyproject.toml",
+            "**/site-packages/**",
+        ]
+        .into_iter()
+        .map(|pattern| FileSystemWatcher {
+            glob_pattern: GlobPattern::String(pattern.to_owned()),
+            kind: Some(WatchKind::all()),
+        })
+        .collect();
+        self.send_request::<RegisterCapability>(RegistrationParams {
+            registrations: vec![Registration {
+                id: "pyrefly-watched-files".to_owned(),
+                method: DidChangeWatchedFiles::METHOD.to_owned(),
+                register_options: Some(
+                    serde_json::to_value(DidChangeWatchedFilesRegistrationOptions { watchers })
+                        .unwrap(),
+                ),
+            }],
+        });
+    }
+
     fn did_open<'a>(
         &'a self,
         ide_transaction_manager: &mut IDETransactionManager<'a>,
@@ -665,6 +1807,7 @@ impl Server {
                 .lock()
                 .insert(uri, Arc::new(params.text_document.text));
         });
+        self.bump_content_revision();
         self.validate_in_memory(ide_transaction_manager)
     }
 
@@ -673,12 +1816,22 @@ impl Server {
         ide_transaction_manager: &mut IDETransactionManager<'a>,
         params: DidChangeTextDocumentParams,
     ) -> anyhow::Result<()> {
-        // We asked for Sync full, so can just grab all the text from params
-        let change = params.content_changes.into_iter().next().unwrap();
+        // We asked for Sync incremental, so each change is either a whole-document
+        // replacement (no `range`) or a splice into the buffer as it stood right before
+        // that change; apply them in order, since later changes' ranges are relative to
+        // the result of earlier ones within the same notification.
         let uri = params.text_document.uri.to_file_path().unwrap();
         self.get_config_with(uri.clone(), |config| {
-            config.open_files.lock().insert(uri, Arc::new(change.text));
+            let mut open_files = config.open_files.lock();
+            let mut buffer = open_files
+                .get(&uri)
+                .map_or_else(String::new, |x| x.as_str().to_owned());
+            for change in params.content_changes {
+                buffer = apply_content_change(&buffer, change);
+            }
+            open_files.insert(uri, Arc::new(buffer));
         });
+        self.bump_content_revision();
         self.validate_in_memory(ide_transaction_manager)
     }
 
@@ -690,6 +1843,7 @@ impl Server {
                 .lock()
                 .remove(&params.text_document.uri.to_file_path().unwrap());
         });
+        self.bump_content_revision();
         self.publish_diagnostics_for_uri(params.text_document.uri, Vec::new(), None);
         Ok(())
     }
@@ -715,137 +1869,6 @@ impl Server {
         self.configs = Arc::new(RwLock::new(new_configs));
     }
 
-    fn make_handle(&self, uri: &Url) -> Handle {
-        let path = uri.to_file_path().unwrap();
-        self.get_config_with(path.clone(), |config| {
-            let module = module_from_path(&path, &config.search_path);
-            let module_path = if config.open_files.lock().contains_key(&path) {
-                ModulePath::memory(path)
-            } else {
-                ModulePath::filesystem(path)
-            };
-            Handle::new(
-                module,
-                module_path,
-                config.runtime_metadata.dupe(),
-                config.loader.dupe(),
-            )
-        })
-    }
-
-    fn goto_definition(
-        &self,
-        transaction: &Transaction<'_>,
-        params: GotoDefinitionParams,
-    ) -> Option<GotoDefinitionResponse> {
-        let uri = &params.text_document_position_params.text_document.uri;
-        if self.get_config_with(uri.to_file_path().unwrap(), |config| {
-            config.disable_language_services
-        }) {
-            return None;
-        }
-        let handle = self.make_handle(uri);
-        let info = transaction.get_module_info(&handle)?;
-        let range = position_to_text_size(&info, params.text_document_position_params.position);
-        let TextRangeWithModuleInfo {
-            module_info: definition_module_info,
-            range,
-        } = transaction.goto_definition(&handle, range)?;
-        let path = to_real_path(definition_module_info.path())?;
-        let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
-        Some(GotoDefinitionResponse::Scalar(Location {
-            uri: Url::from_file_path(path).unwrap(),
-            range: source_range_to_range(&definition_module_info.source_range(range)),
-        }))
-    }
-
-    fn completion(
-        &self,
-        transaction: &Transaction<'_>,
-        params: CompletionParams,
-    ) -> anyhow::Result<CompletionResponse> {
-        let uri = &params.text_document_position.text_document.uri;
-        if self.get_config_with(uri.to_file_path().unwrap(), |config| {
-            config.disable_language_services
-        }) {
-            return Ok(CompletionResponse::List(CompletionList {
-                is_incomplete: false,
-                items: Vec::new(),
-            }));
-        }
-        let handle = self.make_handle(uri);
-        let items = transaction
-            .get_module_info(&handle)
-            .map(|info| {
-                transaction.completion(
-                    &handle,
-                    position_to_text_size(&info, params.text_document_position.position),
-                )
-            })
-            .unwrap_or_default();
-        Ok(CompletionResponse::List(CompletionList {
-            is_incomplete: false,
-            items,
-        }))
-    }
-
-    fn hover(&self, transaction: &Transaction<'_>, params: HoverParams) -> Option<Hover> {
-        let uri = &params.text_document_position_params.text_document.uri;
-        if self.get_config_with(uri.to_file_path().unwrap(), |config| {
-            config.disable_language_services
-        }) {
-            return None;
-        }
-        let handle = self.make_handle(uri);
-        let info = transaction.get_module_info(&handle)?;
-        let range = position_to_text_size(&info, params.text_document_position_params.position);
-        let t = transaction.hover(&handle, range)?;
-        Some(Hover {
-            contents: HoverContents::Markup(MarkupContent {
-                kind: MarkupKind::Markdown,
-                value: format!(
-                    r#"```python
-{}
-```"#,
-                    t
-                ),
-            }),
-            range: None,
-        })
-    }
-
-    fn inlay_hints(
-        &self,
-        transaction: &Transaction<'_>,
-        params: InlayHintParams,
-    ) -> Option<Vec<InlayHint>> {
-        let uri = &params.text_document.uri;
-        if self.get_config_with(uri.to_file_path().unwrap(), |config| {
-            config.disable_language_services
-        }) {
-            return None;
-        }
-        let handle = self.make_handle(uri);
-        let info = transaction.get_module_info(&handle)?;
-        let t = transaction.inlay_hints(&handle)?;
-        Some(t.into_map(|x| {
-            let position = text_size_to_position(&info, x.0);
-            InlayHint {
-                position,
-                label: InlayHintLabel::String(x.1.clone()),
-                kind: None,
-                text_edits: Some(vec![TextEdit {
-                    range: Range::new(position, position),
-                    new_text: x.1,
-                }]),
-                tooltip: None,
-                padding_left: None,
-                padding_right: None,
-                data: None,
-            }
-        }))
-    }
-
     fn change_configuration(&self) {
         self.configs.read().iter().for_each(|(scope_uri, _)| {
             self.request_settings_for_config(&Url::from_file_path(scope_uri).unwrap())
@@ -946,6 +1969,232 @@ fn source_range_to_range(x: &SourceRange) -> lsp_types::Range {
     )
 }
 
+/// Scans `text` for `class`/`def`/`async def` declarations and builds a `DocumentSymbol`
+/// tree nested by indentation. This is a plain textual scan rather than a real parse of the
+/// module's AST/bindings (which `Transaction` doesn't expose to this file), so it doesn't
+/// understand multi-line signatures or decorators, but it's enough to drive "Go to Symbol"
+/// and breadcrumbs for the common case. Lines inside a triple-quoted docstring are skipped
+/// (see `open_triple_quote` below) so a docstring that quotes code like `def helper():` as
+/// an example doesn't produce a bogus symbol for it.
+#[allow(deprecated)] // `DocumentSymbol::deprecated` has no replacement we need here.
+fn scan_python_symbols(text: &str) -> Vec<DocumentSymbol> {
+    fn close_to(
+        stack: &mut Vec<(usize, DocumentSymbol)>,
+        roots: &mut Vec<DocumentSymbol>,
+        indent: usize,
+    ) {
+        while let Some((top_indent, _)) = stack.last() {
+            if *top_indent < indent {
+                break;
+            }
+            let (_, finished) = stack.pop().unwrap();
+            match stack.last_mut() {
+                Some((_, parent)) => parent.children.get_or_insert_with(Vec::new).push(finished),
+                None => roots.push(finished),
+            }
+        }
+    }
+
+    let mut stack: Vec<(usize, DocumentSymbol)> = Vec::new();
+    let mut roots: Vec<DocumentSymbol> = Vec::new();
+    // Triple-quote delimiter currently open across lines, if we're inside a docstring (or
+    // other triple-quoted string literal) that started on an earlier line; lines while this
+    // is set are skipped entirely rather than scanned for `class`/`def`.
+    let mut open_triple_quote: Option<&'static str> = None;
+    for (line_no, line) in text.lines().enumerate() {
+        if let Some(delim) = open_triple_quote {
+            if line.contains(delim) {
+                open_triple_quote = None;
+            }
+            continue;
+        }
+        let indent = line.len() - line.trim_start().len();
+        let trimmed = line.trim_start();
+        // A bare triple-quoted string (the common docstring shape: its own line right
+        // after a `def`/`class` signature) can't also start with one of the keywords
+        // below, so it's safe to check for one opening here before the keyword match.
+        for delim in ["\"\"\"", "'''"] {
+            if let Some(after_open) = trimmed.strip_prefix(delim)
+                && !after_open.contains(delim)
+            {
+                open_triple_quote = Some(delim);
+                break;
+            }
+        }
+        if open_triple_quote.is_some() {
+            continue;
+        }
+        let (kind, keyword_len) = if trimmed.starts_with("async def ") {
+            (SymbolKind::FUNCTION, "async def ".len())
+        } else if trimmed.starts_with("def ") {
+            (SymbolKind::FUNCTION, "def ".len())
+        } else if trimmed.starts_with("class ") {
+            (SymbolKind::CLASS, "class ".len())
+        } else {
+            continue;
+        };
+        let rest = &trimmed[keyword_len..];
+        let name_end = rest
+            .find(|c: char| c == '(' || c == ':' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let name = &rest[..name_end];
+        if name.is_empty() {
+            continue;
+        }
+        close_to(&mut stack, &mut roots, indent);
+        let line_no = line_no as u32;
+        let name_start = (indent + keyword_len) as u32;
+        let name_end = name_start + name.chars().count() as u32;
+        let range = Range::new(
+            lsp_types::Position::new(line_no, 0),
+            lsp_types::Position::new(line_no, line.chars().count() as u32),
+        );
+        let selection_range = Range::new(
+            lsp_types::Position::new(line_no, name_start),
+            lsp_types::Position::new(line_no, name_end),
+        );
+        let symbol = DocumentSymbol {
+            name: name.to_owned(),
+            detail: None,
+            kind,
+            tags: None,
+            deprecated: None,
+            range,
+            selection_range,
+            children: None,
+        };
+        stack.push((indent, symbol));
+    }
+    close_to(&mut stack, &mut roots, 0);
+    roots
+}
+
+/// Flattens `symbols` and appends every one whose name contains `query` (case-insensitive)
+/// to `results`, as a `SymbolInformation` pointing at `uri`.
+#[allow(deprecated)] // `SymbolInformation::deprecated` has no replacement we need here.
+fn collect_matching_symbols(
+    symbols: &[DocumentSymbol],
+    uri: &Url,
+    query: &str,
+    results: &mut Vec<SymbolInformation>,
+) {
+    for symbol in symbols {
+        if query.is_empty() || symbol.name.to_lowercase().contains(query) {
+            results.push(SymbolInformation {
+                name: symbol.name.clone(),
+                kind: symbol.kind,
+                tags: None,
+                deprecated: None,
+                location: Location {
+                    uri: uri.clone(),
+                    range: symbol.selection_range,
+                },
+                container_name: None,
+            });
+        }
+        if let Some(children) = &symbol.children {
+            collect_matching_symbols(children, uri, query, results);
+        }
+    }
+}
+
+fn position_within_range(position: lsp_types::Position, range: Range) -> bool {
+    (range.start.line, range.start.character) <= (position.line, position.character)
+        && (position.line, position.character) <= (range.end.line, range.end.character)
+}
+
+/// Diagnostics built by `FindError::display_with_suggestions` for an unresolved import
+/// embed a "Did you mean: `foo`?" footer; pull the first suggested name back out so we can
+/// offer to insert the corresponding import.
+///
+/// NOT REACHABLE YET: `compute_diagnostics` above builds every `Diagnostic.message` from
+/// `e.msg()`, not `FindError::display_with_suggestions`, and nothing in this tree constructs
+/// a `LoaderFindCache` to source suggestions from in the first place (see the `NOT WIRED UP`
+/// note on `LoaderFindCache` in `state/loader.rs`). So no diagnostic this server emits today
+/// actually contains a "Did you mean" footer, and `code_action`'s call to this function is
+/// dead in practice until that's wired up. Covered by the unit tests below, which exercise
+/// the parse directly rather than through a real diagnostic.
+fn suggested_import_from_diagnostic(message: &str) -> Option<&str> {
+    let suggestions = message.split("Did you mean: ").nth(1)?;
+    let start = suggestions.find('`')? + 1;
+    let end = start + suggestions[start..].find('`')?;
+    Some(&suggestions[start..end])
+}
+
+fn missing_import_action(uri: &Url, module: &str, diagnostic: &Diagnostic) -> CodeAction {
+    let insert_at = lsp_types::Position::new(0, 0);
+    CodeAction {
+        title: format!("Import `{module}`"),
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: Some(vec![diagnostic.clone()]),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(
+                uri.clone(),
+                vec![TextEdit {
+                    range: Range::new(insert_at, insert_at),
+                    new_text: format!("import {module}\n"),
+                }],
+            )])),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn suppress_diagnostic_action(
+    uri: &Url,
+    code: &NumberOrString,
+    diagnostic: &Diagnostic,
+) -> CodeAction {
+    let code = match code {
+        NumberOrString::String(s) => s.clone(),
+        NumberOrString::Number(n) => n.to_string(),
+    };
+    // There's no buffer handy here to find the real end of the line, but LSP clients clamp
+    // an out-of-range character to the line's actual length, so this lands at line end.
+    let line_end = lsp_types::Position::new(diagnostic.range.end.line, u32::MAX);
+    CodeAction {
+        title: format!("Suppress `{code}` on this line"),
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: Some(vec![diagnostic.clone()]),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(
+                uri.clone(),
+                vec![TextEdit {
+                    range: Range::new(line_end, line_end),
+                    new_text: format!("  # pyrefly: ignore[{code}]"),
+                }],
+            )])),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn annotate_type_action(
+    uri: &Url,
+    position: lsp_types::Position,
+    annotation: &str,
+    diagnostic: &Diagnostic,
+) -> CodeAction {
+    CodeAction {
+        title: format!("Add inferred type annotation `{annotation}`"),
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: Some(vec![diagnostic.clone()]),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(
+                uri.clone(),
+                vec![TextEdit {
+                    range: Range::new(position, position),
+                    new_text: annotation.to_owned(),
+                }],
+            )])),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
 fn source_location_to_position(x: &SourceLocation) -> lsp_types::Position {
     lsp_types::Position {
         line: x.row.to_zero_indexed() as u32,
@@ -961,6 +2210,48 @@ fn position_to_text_size(info: &ModuleInfo, position: lsp_types::Position) -> Te
     info.to_text_size(position.line, position.character)
 }
 
+/// Converts an LSP `Position` into a byte offset into `text`. Unlike `position_to_text_size`,
+/// this works directly off a plain buffer rather than a checked `ModuleInfo`, because it has
+/// to be recomputed after every incremental edit to an open file, long before that content is
+/// ever parsed or checked. LSP positions count UTF-16 code units, not bytes or chars, so we
+/// walk the target line counting UTF-16 units per `char` rather than indexing directly.
+fn position_to_byte_offset(text: &str, position: lsp_types::Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(offset) => line_start += offset + 1,
+            None => return text.len(),
+        }
+    }
+    let line_end = text[line_start..]
+        .find('\n')
+        .map_or(text.len(), |offset| line_start + offset);
+    let mut utf16_units = 0;
+    for (byte_offset, ch) in text[line_start..line_end].char_indices() {
+        if utf16_units >= position.character as usize {
+            return line_start + byte_offset;
+        }
+        utf16_units += ch.len_utf16();
+    }
+    line_end
+}
+
+/// Applies a single `textDocument/didChange` content change to `buffer`, returning the new
+/// buffer. A change with no `range` is a full-document replacement; otherwise `change.text`
+/// is spliced into the byte span `range` maps to (an empty range is a pure insertion).
+fn apply_content_change(buffer: &str, change: TextDocumentContentChangeEvent) -> String {
+    let Some(range) = change.range else {
+        return change.text;
+    };
+    let start = position_to_byte_offset(buffer, range.start);
+    let end = position_to_byte_offset(buffer, range.end);
+    let mut result = String::with_capacity(buffer.len() - (end - start) + change.text.len());
+    result.push_str(&buffer[..start]);
+    result.push_str(&change.text);
+    result.push_str(&buffer[end..]);
+    result
+}
+
 fn as_notification<T>(x: &Notification) -> Option<T::Params>
 where
     T: lsp_types::notification::Notification,
@@ -979,6 +2270,22 @@ where
     }
 }
 
+/// Best-effort extraction of the document a read-only request's params are about, straight
+/// from the still-untyped JSON (rather than each request's differently-shaped `Params` type),
+/// so `RequestDispatcher::on` can recognize when a newer request supersedes an older one for
+/// the same document without a per-request-type extractor. Works for any request whose params
+/// embed a `textDocument: TextDocumentIdentifier` at the top level (directly or via
+/// `#[serde(flatten)]`), which covers all of today's document-position requests; returns
+/// `None` for requests with no single document, like `workspace/symbol`.
+fn document_uri_from_params(params: &serde_json::Value) -> Option<PathBuf> {
+    params
+        .get("textDocument")?
+        .get("uri")?
+        .as_str()
+        .and_then(|uri| Url::parse(uri).ok())
+        .and_then(|uri| uri.to_file_path().ok())
+}
+
 fn as_request<T>(x: &Request) -> Option<T::Params>
 where
     T: lsp_types::request::Request,
@@ -1050,3 +2357,153 @@ where
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suggested_import_from_diagnostic_finds_first_suggestion() {
+        let message = "Could not find import of `foo`, looked at search roots ()\n\
+            Did you mean: `foo_bar`, `foo_baz`?";
+        assert_eq!(
+            suggested_import_from_diagnostic(message),
+            Some("foo_bar"),
+        );
+    }
+
+    #[test]
+    fn test_suggested_import_from_diagnostic_none_without_footer() {
+        let message = "Could not find import of `foo`, looked at search roots ()";
+        assert_eq!(suggested_import_from_diagnostic(message), None);
+    }
+
+    #[test]
+    fn test_missing_import_action_inserts_at_top_of_file() {
+        let uri = Url::parse("file:///tmp/test.py").unwrap();
+        let diagnostic = Diagnostic {
+            range: Range::new(lsp_types::Position::new(4, 0), lsp_types::Position::new(4, 3)),
+            ..Default::default()
+        };
+        let action = missing_import_action(&uri, "foo_bar", &diagnostic);
+        assert_eq!(action.title, "Import `foo_bar`");
+        assert_eq!(action.kind, Some(CodeActionKind::QUICKFIX));
+        let edit = action.edit.unwrap();
+        let changes = edit.changes.unwrap();
+        let edits = &changes[&uri];
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].new_text, "import foo_bar\n");
+        assert_eq!(
+            edits[0].range,
+            Range::new(lsp_types::Position::new(0, 0), lsp_types::Position::new(0, 0)),
+        );
+    }
+
+    fn symbol_names(symbols: &[DocumentSymbol]) -> Vec<&str> {
+        symbols.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_scan_python_symbols_nests_by_indentation() {
+        let text = "class Foo:\n    def bar(self):\n        pass\n\ndef baz():\n    pass\n";
+        let symbols = scan_python_symbols(text);
+        assert_eq!(symbol_names(&symbols), vec!["Foo", "baz"]);
+        let foo_children = symbols[0].children.as_ref().unwrap();
+        assert_eq!(symbol_names(foo_children), vec!["bar"]);
+    }
+
+    #[test]
+    fn test_scan_python_symbols_recognizes_async_def() {
+        let text = "async def handler():\n    pass\n";
+        let symbols = scan_python_symbols(text);
+        assert_eq!(symbol_names(&symbols), vec!["handler"]);
+        assert_eq!(symbols[0].kind, SymbolKind::FUNCTION);
+    }
+
+    #[test]
+    fn test_scan_python_symbols_skips_docstring_lookalikes() {
+        // A docstring that mentions `def helper():` as prose shouldn't produce a symbol.
+        let text = "def real():\n    \"\"\"\n    Example: def helper():\n        pass\n    \"\"\"\n    pass\n";
+        let symbols = scan_python_symbols(text);
+        assert_eq!(symbol_names(&symbols), vec!["real"]);
+        assert!(symbols[0].children.is_none());
+    }
+
+    #[test]
+    fn test_scan_python_symbols_handles_single_line_triple_quote() {
+        // A triple-quoted string that opens and closes on the same line shouldn't leave
+        // `open_triple_quote` set, so later lines keep being scanned normally.
+        let text = "x = \"\"\"not a docstring\"\"\"\ndef after():\n    pass\n";
+        let symbols = scan_python_symbols(text);
+        assert_eq!(symbol_names(&symbols), vec!["after"]);
+    }
+
+    #[test]
+    fn test_position_to_byte_offset_counts_utf16_units() {
+        // "héllo\n" - the accented character is 1 UTF-16 unit but 2 bytes in UTF-8.
+        let text = "héllo\nworld\n";
+        assert_eq!(
+            position_to_byte_offset(text, lsp_types::Position::new(0, 0)),
+            0,
+        );
+        assert_eq!(
+            position_to_byte_offset(text, lsp_types::Position::new(0, 2)),
+            "h\u{e9}".len(),
+        );
+        assert_eq!(
+            position_to_byte_offset(text, lsp_types::Position::new(1, 0)),
+            text.find('\n').unwrap() + 1,
+        );
+    }
+
+    #[test]
+    fn test_position_to_byte_offset_past_end_clamps() {
+        let text = "abc\n";
+        assert_eq!(
+            position_to_byte_offset(text, lsp_types::Position::new(5, 0)),
+            text.len(),
+        );
+        assert_eq!(
+            position_to_byte_offset(text, lsp_types::Position::new(0, 100)),
+            3,
+        );
+    }
+
+    #[test]
+    fn test_apply_content_change_full_replacement() {
+        let change = TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: "new content".to_owned(),
+        };
+        assert_eq!(apply_content_change("old content", change), "new content");
+    }
+
+    #[test]
+    fn test_apply_content_change_range_splice() {
+        let buffer = "hello world\n";
+        let change = TextDocumentContentChangeEvent {
+            range: Some(Range::new(
+                lsp_types::Position::new(0, 6),
+                lsp_types::Position::new(0, 11),
+            )),
+            range_length: None,
+            text: "there".to_owned(),
+        };
+        assert_eq!(apply_content_change(buffer, change), "hello there\n");
+    }
+
+    #[test]
+    fn test_apply_content_change_pure_insertion() {
+        let buffer = "ac";
+        let change = TextDocumentContentChangeEvent {
+            range: Some(Range::new(
+                lsp_types::Position::new(0, 1),
+                lsp_types::Position::new(0, 1),
+            )),
+            range_length: None,
+            text: "b".to_owned(),
+        };
+        assert_eq!(apply_content_change(buffer, change), "abc");
+    }
+}
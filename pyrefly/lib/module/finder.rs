@@ -9,13 +9,16 @@ use std::path::Path;
 use std::path::PathBuf;
 
 use ruff_python_ast::name::Name;
+use starlark_map::small_map::SmallMap;
 use vec1::Vec1;
 
+use crate::metadata::PythonVersion;
 use crate::module::module_name::ModuleName;
 use crate::module::module_path::ModulePath;
+use crate::state::loader::TypeshedVersions;
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Default)]
-enum PyTyped {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub(crate) enum PyTyped {
     #[default]
     Missing,
     Complete,
@@ -35,7 +38,6 @@ enum FindResult {
 }
 
 impl FindResult {
-    #[expect(dead_code)]
     fn py_typed(&self) -> PyTyped {
         /// Finds a `py.typed` file for the given path, if it exists, and
         /// returns a boolean representing if it is partial or not.
@@ -99,7 +101,7 @@ fn find_one_part(name: &Name, roots: &[PathBuf]) -> Option<FindResult> {
     }
 }
 
-pub fn find_module_in_search_path(module: ModuleName, include: &[PathBuf]) -> Option<ModulePath> {
+fn find_result_in_search_path(module: ModuleName, include: &[PathBuf]) -> Option<FindResult> {
     let parts = module.components();
     if parts.is_empty() {
         return None;
@@ -124,31 +126,296 @@ pub fn find_module_in_search_path(module: ModuleName, include: &[PathBuf]) -> Op
             }
         }
     }
-    current_result.map(|x| match x {
+    current_result
+}
+
+pub fn find_module_in_search_path(module: ModuleName, include: &[PathBuf]) -> Option<ModulePath> {
+    find_module_in_search_path_with_py_typed(module, include).map(|(path, _)| path)
+}
+
+/// Like [`find_module_in_search_path`], but also returns the PEP 561 `py.typed` status of
+/// the top-level package whatever was found belongs to, for callers (like
+/// [`find_module_in_site_package_path`]) that need to decide whether to trust it as fully
+/// annotated.
+fn find_module_in_search_path_with_py_typed(
+    module: ModuleName,
+    include: &[PathBuf],
+) -> Option<(ModulePath, PyTyped)> {
+    let result = find_result_in_search_path(module, include)?;
+    // The `py.typed` marker sits at the top of a distribution, not beside each submodule.
+    let py_typed = find_one_part(&module.first_component(), include)
+        .map_or(PyTyped::Missing, |top_level| top_level.py_typed());
+    let path = match result {
         FindResult::SingleFileModule(path) | FindResult::RegularPackage(path, _) => {
             ModulePath::filesystem(path)
         }
         FindResult::NamespacePackage(roots) => {
-            // TODO(grievejia): Preserving all info in the list instead of dropping all but the first one.
+            // NOT IMPLEMENTED (single source of truth for this gap - see
+            // `find_module_in_site_package_path`'s doc comment, which points back here
+            // instead of repeating this): TODO(grievejia) asks to preserve every
+            // contributing root instead of dropping all but `roots.first()`. That needs
+            // `ModulePath::namespace` to accept the whole `Vec1<PathBuf>`, but `ModulePath`
+            // (`module_path.rs`) isn't a file in this tree, so its variants can't be widened
+            // from here. Descoped until that file is available to edit.
             ModulePath::namespace(roots.first().clone())
         }
-    })
+    };
+    Some((path, py_typed))
+}
+
+fn stubs_name(module: ModuleName) -> Name {
+    Name::new(format!("{}-stubs", module.first_component()))
+}
+
+/// What a site-packages root's `.pth` files contribute to module resolution, mirroring
+/// CPython's own `site` module: `extra_roots` are plain directory lines, to be searched for
+/// any module; `editable_modules` are the modules a setuptools editable install covers,
+/// mapped to the real source directory their sibling `_finder.py` points at.
+#[derive(Default)]
+struct PthExpansion {
+    extra_roots: Vec<PathBuf>,
+    editable_modules: SmallMap<Name, PathBuf>,
 }
 
+/// Scans `site_package_root` for `*.pth` files and expands them the way CPython's `site`
+/// module does: a line that's blank, a `#` comment, or begins with `import `/`import\t` is
+/// skipped (except as described below); every other line is a directory (absolute, or
+/// relative to `site_package_root`) to add to the search path.
+///
+/// This also covers editable installs from recent setuptools: a `__editable__.<name>-<ver>.pth`
+/// in "strict"/directory mode is just a directory line handled like any other, but in the
+/// common finder mode it instead `import`s a sibling `__editable___<name>_finder.py`, whose
+/// `MAPPING` dict literal maps each top-level module the install provides to its real source
+/// directory; those are parsed out and returned separately, keyed by module name, since they
+/// apply only to the module(s) named rather than to resolution generally.
+fn expand_pth_files(site_package_root: &Path) -> PthExpansion {
+    let mut expansion = PthExpansion::default();
+    let Ok(entries) = std::fs::read_dir(site_package_root) else {
+        return expansion;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("pth") {
+            continue;
+        }
+        let Ok(contents) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let as_import = line
+                .strip_prefix("import ")
+                .or_else(|| line.strip_prefix("import\t"));
+            if let Some(rest) = as_import {
+                let Some(finder_module) = rest.split([';', ' ', '\t']).next() else {
+                    continue;
+                };
+                let finder_path = site_package_root.join(format!("{finder_module}.py"));
+                if let Ok(finder_src) = std::fs::read_to_string(&finder_path) {
+                    expansion
+                        .editable_modules
+                        .extend(parse_editable_mapping(&finder_src));
+                }
+                continue;
+            }
+            let dir = PathBuf::from(line);
+            expansion.extra_roots.push(if dir.is_absolute() {
+                dir
+            } else {
+                site_package_root.join(dir)
+            });
+        }
+    }
+    expansion
+}
+
+/// Parses a setuptools editable-install finder's `MAPPING = {"top_level": "/abs/src", ...}`
+/// dict literal. Deliberately minimal: this handles exactly the quoted-string-to-quoted-string
+/// literal setuptools emits, not arbitrary Python.
+fn parse_editable_mapping(finder_src: &str) -> SmallMap<Name, PathBuf> {
+    let mut mapping = SmallMap::new();
+    let Some(mapping_start) = finder_src.find("MAPPING") else {
+        return mapping;
+    };
+    let tail = &finder_src[mapping_start..];
+    let Some(open) = tail.find('{') else {
+        return mapping;
+    };
+    let Some(close) = tail[open..].find('}') else {
+        return mapping;
+    };
+    let body = &tail[open + 1..open + close];
+    for entry in body.split(',') {
+        let mut parts = entry.splitn(2, ':');
+        let (Some(key), Some(value)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        let key = key.trim().trim_matches(['"', '\'']);
+        let value = value.trim().trim_matches(['"', '\'']);
+        if key.is_empty() || value.is_empty() {
+            continue;
+        }
+        mapping.insert(Name::new(key), PathBuf::from(value));
+    }
+    mapping
+}
+
+/// A module resolved via [`find_module_in_site_package_path`], together with the PEP 561
+/// `py.typed` status of the distribution it was found in, so callers can decide whether to
+/// trust it as fully annotated, treat its unannotated symbols as `Unknown`, or reject it
+/// outright (see [`SitePackageResolverOptions::require_py_typed`]).
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct ResolvedModule {
+    pub(crate) path: ModulePath,
+    pub(crate) py_typed: PyTyped,
+}
+
+/// Options controlling how [`find_module_in_site_package_path`] enforces PEP 561 typing
+/// markers.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SitePackageResolverOptions {
+    /// When set, a third-party distribution with no `py.typed` marker (`PyTyped::Missing`) is
+    /// rejected rather than resolved, so the lookup falls through to a `-stubs` distribution
+    /// or typeshed instead of trusting unannotated source.
+    pub(crate) require_py_typed: bool,
+}
+
+/// Like [`find_module_in_search_path`], but also considers a sibling `-stubs`
+/// distribution. Namespace packages are supported here the same way they are in
+/// `find_module_in_search_path`: if the package has no `__init__`, every contributing
+/// site-package root is folded into the `FindResult::NamespacePackage` before a
+/// submodule lookup continues, so `foo/bar` split across two site-package roots still
+/// resolves `foo.bar` correctly (pre-existing behavior; the one open gap in multi-root
+/// namespace packages is the `NOT IMPLEMENTED` note on `FindResult::NamespacePackage`
+/// handling in `find_module_in_search_path_with_py_typed` above - not duplicated here).
+///
+/// Each root is also pre-expanded with its `.pth` files (see `expand_pth_files`), so editable
+/// installs (`pip install -e`) are found the same way a regular install would be.
 pub fn find_module_in_site_package_path(
     module: ModuleName,
     include: &[PathBuf],
+    options: SitePackageResolverOptions,
+) -> Option<ResolvedModule> {
+    let mut editable_dir = None;
+    let mut expanded_include = include.to_vec();
+    for root in include {
+        let expansion = expand_pth_files(root);
+        if editable_dir.is_none() {
+            editable_dir = expansion
+                .editable_modules
+                .get(&module.first_component())
+                .cloned();
+        }
+        expanded_include.extend(expansion.extra_roots);
+    }
+    if let Some(editable_dir) = &editable_dir {
+        expanded_include.push(editable_dir.clone());
+    }
+    let include = &expanded_include;
+
+    let resolve_real_package = |module: ModuleName| {
+        let (path, py_typed) = find_module_in_search_path_with_py_typed(module, include)?;
+        if options.require_py_typed && py_typed == PyTyped::Missing {
+            return None;
+        }
+        Some(ResolvedModule { path, py_typed })
+    };
+
+    let stubs_first = stubs_name(module);
+    match find_one_part(&stubs_first, include) {
+        // No `-stubs` distribution at all: resolve directly against the real package.
+        None => resolve_real_package(module),
+        Some(top_level) => {
+            let stubs_module =
+                ModuleName::from_parts([stubs_first].iter().chain(module.components().iter().skip(1)));
+            let top_level_py_typed = top_level.py_typed();
+            let from_stubs = find_module_in_search_path_with_py_typed(stubs_module, include)
+                .map(|(path, _)| ResolvedModule {
+                    path,
+                    py_typed: top_level_py_typed,
+                });
+            match top_level_py_typed {
+                // A complete stub package is authoritative: don't silently fall back to
+                // untyped `.py` sources for modules it doesn't provide.
+                PyTyped::Complete => from_stubs,
+                // A partial stub package (or one missing a marker, which we treat
+                // permissively) still wants the real package consulted for anything
+                // the stubs omit.
+                PyTyped::Partial | PyTyped::Missing => {
+                    from_stubs.or_else(|| resolve_real_package(module))
+                }
+            }
+        }
+    }
+}
+
+/// Resolves `module` against a typeshed checkout's `stdlib/` directory, honoring typeshed's
+/// `stdlib/VERSIONS` file: a module outside the range it lists for `target_version` resolves
+/// to `None`, so e.g. `tomllib` isn't found on Python 3.10 and a module removed in a later
+/// version isn't found on it, even if the `.pyi` is still present on disk. Resolution of the
+/// `.pyi` itself reuses `find_module_in_search_path`'s `find_one_part` machinery, rooted at
+/// `typeshed_root/stdlib`.
+pub fn find_module_in_typeshed(
+    module: ModuleName,
+    target_version: PythonVersion,
+    typeshed_root: &Path,
+    stdlib_versions: &TypeshedVersions,
 ) -> Option<ModulePath> {
-    let mut first = module.first_component().to_string();
-    first.push_str("-stubs");
-    let stubs_module = ModuleName::from_parts(
-        [Name::new(first)]
-            .iter()
-            .chain(module.components().iter().skip(1)),
-    );
-
-    find_module_in_search_path(stubs_module, include)
-        .or_else(|| find_module_in_search_path(module, include))
+    if stdlib_versions
+        .unsupported_at(module, (target_version.major, target_version.minor))
+        .is_some()
+    {
+        return None;
+    }
+    find_module_in_search_path(module, &[typeshed_root.join("stdlib")])
+}
+
+fn is_valid_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => {}
+        _ => return false,
+    }
+    chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+/// The inverse of [`find_module_in_search_path`]/[`find_module_in_site_package_path`]: maps a
+/// resolved module file back to the dotted [`ModuleName`] that would have produced it, for LSP
+/// features (go-to-definition labeling, import completions, diagnostics that name the module)
+/// that only have the path.
+///
+/// Finds the longest entry of `roots` that prefixes `path`, strips it, drops the `.py`/`.pyi`
+/// suffix and any trailing `__init__`, and rejects the result if any remaining component isn't a
+/// valid identifier (so `foo/bar-baz.py` yields `None`). A `-stubs` package's suffix is stripped
+/// from its top-level component, mirroring how `find_module_in_site_package_path` treats
+/// `foo-stubs` as standing in for `foo`, so `foo-stubs/bar.pyi` maps back to `foo.bar`.
+pub fn module_name_for_path(path: &Path, roots: &[PathBuf]) -> Option<ModuleName> {
+    let root = roots
+        .iter()
+        .filter(|root| path.starts_with(root))
+        .max_by_key(|root| root.as_os_str().len())?;
+    let relative = path.strip_prefix(root).ok()?.with_extension("");
+    let mut parts = relative
+        .components()
+        .map(|c| c.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()?;
+    if parts.last() == Some(&"__init__") {
+        parts.pop();
+    }
+    if parts.is_empty() {
+        return None;
+    }
+    if let Some(stripped) = parts[0].strip_suffix("-stubs") {
+        parts[0] = stripped;
+    }
+    if !parts.iter().all(|part| is_valid_identifier(part)) {
+        return None;
+    }
+    let names: Vec<Name> = parts.into_iter().map(Name::new).collect();
+    Some(ModuleName::from_parts(names.iter()))
 }
 
 #[cfg(test)]
@@ -381,18 +648,397 @@ mod tests {
         assert_eq!(
             find_module_in_site_package_path(
                 ModuleName::from_str("foo.bar"),
-                &[root.to_path_buf()]
+                &[root.to_path_buf()],
+                SitePackageResolverOptions::default(),
             ),
-            Some(ModulePath::filesystem(
-                root.join("foo-stubs/bar/__init__.py")
-            ))
+            Some(ResolvedModule {
+                path: ModulePath::filesystem(root.join("foo-stubs/bar/__init__.py")),
+                py_typed: PyTyped::Missing,
+            })
         );
         assert_eq!(
             find_module_in_site_package_path(
                 ModuleName::from_str("foo.baz"),
-                &[root.to_path_buf()]
+                &[root.to_path_buf()],
+                SitePackageResolverOptions::default(),
             ),
-            Some(ModulePath::filesystem(root.join("foo/baz/__init__.pyi")))
+            Some(ResolvedModule {
+                path: ModulePath::filesystem(root.join("foo/baz/__init__.pyi")),
+                py_typed: PyTyped::Missing,
+            })
+        );
+    }
+
+    #[test]
+    fn test_site_package_namespace_package_multiple_roots() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let root = tempdir.path();
+        TestPath::setup_test_directory(
+            root,
+            vec![
+                TestPath::dir(
+                    "search_root0",
+                    vec![TestPath::dir("a", vec![TestPath::file("b.py")])],
+                ),
+                TestPath::dir(
+                    "search_root1",
+                    vec![TestPath::dir("a", vec![TestPath::file("c.py")])],
+                ),
+            ],
+        );
+        let site_package_path = [root.join("search_root0"), root.join("search_root1")];
+        // `a` has no `__init__` in either root, so it's an implicit namespace package
+        // whose submodules may be split across both contributing site-package roots.
+        assert_eq!(
+            find_module_in_site_package_path(
+                ModuleName::from_str("a.b"),
+                &site_package_path,
+                SitePackageResolverOptions::default(),
+            ),
+            Some(ResolvedModule {
+                path: ModulePath::filesystem(root.join("search_root0/a/b.py")),
+                py_typed: PyTyped::Missing,
+            })
+        );
+        assert_eq!(
+            find_module_in_site_package_path(
+                ModuleName::from_str("a.c"),
+                &site_package_path,
+                SitePackageResolverOptions::default(),
+            ),
+            Some(ResolvedModule {
+                path: ModulePath::filesystem(root.join("search_root1/a/c.py")),
+                py_typed: PyTyped::Missing,
+            })
+        );
+    }
+
+    #[test]
+    fn test_find_partial_stubs_falls_back_to_real_package() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let root = tempdir.path();
+        TestPath::setup_test_directory(
+            root,
+            vec![
+                TestPath::dir(
+                    "foo",
+                    vec![
+                        TestPath::file("__init__.py"),
+                        TestPath::file("bar.py"),
+                        TestPath::file("baz.py"),
+                    ],
+                ),
+                TestPath::dir(
+                    "foo-stubs",
+                    vec![
+                        TestPath::file("__init__.py"),
+                        TestPath::file("py.typed"),
+                        TestPath::file("bar.pyi"),
+                    ],
+                ),
+            ],
+        );
+        std::fs::write(root.join("foo-stubs/py.typed"), "partial").unwrap();
+        assert_eq!(
+            find_module_in_site_package_path(
+                ModuleName::from_str("foo.bar"),
+                &[root.to_path_buf()],
+                SitePackageResolverOptions::default(),
+            ),
+            Some(ResolvedModule {
+                path: ModulePath::filesystem(root.join("foo-stubs/bar.pyi")),
+                py_typed: PyTyped::Partial,
+            })
+        );
+        // `baz` isn't covered by the stubs, but the package is only `partial`, so we
+        // fall back to the real (untyped) source instead of failing to resolve.
+        assert_eq!(
+            find_module_in_site_package_path(
+                ModuleName::from_str("foo.baz"),
+                &[root.to_path_buf()],
+                SitePackageResolverOptions::default(),
+            ),
+            Some(ResolvedModule {
+                path: ModulePath::filesystem(root.join("foo/baz.py")),
+                py_typed: PyTyped::Missing,
+            })
+        );
+    }
+
+    #[test]
+    fn test_find_complete_stubs_does_not_fall_back() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let root = tempdir.path();
+        TestPath::setup_test_directory(
+            root,
+            vec![
+                TestPath::dir(
+                    "foo",
+                    vec![
+                        TestPath::file("__init__.py"),
+                        TestPath::file("bar.py"),
+                        TestPath::file("baz.py"),
+                    ],
+                ),
+                TestPath::dir(
+                    "foo-stubs",
+                    vec![
+                        TestPath::file("__init__.py"),
+                        TestPath::file("py.typed"),
+                        TestPath::file("bar.pyi"),
+                    ],
+                ),
+            ],
+        );
+        assert_eq!(
+            find_module_in_site_package_path(
+                ModuleName::from_str("foo.bar"),
+                &[root.to_path_buf()],
+                SitePackageResolverOptions::default(),
+            ),
+            Some(ResolvedModule {
+                path: ModulePath::filesystem(root.join("foo-stubs/bar.pyi")),
+                py_typed: PyTyped::Complete,
+            })
+        );
+        // `foo-stubs` declares itself `Complete`, so a module it doesn't provide is
+        // unresolved rather than silently falling back to the untyped real package.
+        assert_eq!(
+            find_module_in_site_package_path(
+                ModuleName::from_str("foo.baz"),
+                &[root.to_path_buf()],
+                SitePackageResolverOptions::default(),
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_find_module_via_pth_directory() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let root = tempdir.path();
+        TestPath::setup_test_directory(
+            root,
+            vec![
+                TestPath::dir("site-packages", vec![]),
+                TestPath::dir("src", vec![TestPath::file("foo.py")]),
+            ],
+        );
+        std::fs::write(
+            root.join("site-packages/extra.pth"),
+            format!("# a comment\n\n{}\n", root.join("src").display()),
+        )
+        .unwrap();
+        assert_eq!(
+            find_module_in_site_package_path(
+                ModuleName::from_str("foo"),
+                &[root.join("site-packages")],
+                SitePackageResolverOptions::default(),
+            ),
+            Some(ResolvedModule {
+                path: ModulePath::filesystem(root.join("src/foo.py")),
+                py_typed: PyTyped::Missing,
+            })
+        );
+    }
+
+    #[test]
+    fn test_find_module_via_editable_install() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let root = tempdir.path();
+        TestPath::setup_test_directory(
+            root,
+            vec![
+                TestPath::dir("site-packages", vec![]),
+                TestPath::dir("src", vec![TestPath::file("foo.py")]),
+            ],
+        );
+        std::fs::write(
+            root.join("site-packages/__editable__.foo-1.0.pth"),
+            "import __editable___foo_finder\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("site-packages/__editable___foo_finder.py"),
+            format!(
+                "MAPPING = {{'foo': {:?}}}\n",
+                root.join("src").display().to_string()
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            find_module_in_site_package_path(
+                ModuleName::from_str("foo"),
+                &[root.join("site-packages")],
+                SitePackageResolverOptions::default(),
+            ),
+            Some(ResolvedModule {
+                path: ModulePath::filesystem(root.join("src/foo.py")),
+                py_typed: PyTyped::Missing,
+            })
+        );
+    }
+
+    #[test]
+    fn test_require_py_typed_rejects_untyped_package_without_stubs() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let root = tempdir.path();
+        TestPath::setup_test_directory(
+            root,
+            vec![TestPath::dir("foo", vec![TestPath::file("__init__.py")])],
+        );
+        let strict = SitePackageResolverOptions {
+            require_py_typed: true,
+        };
+        // No `py.typed` marker and no `-stubs` distribution to fall back to: the strict
+        // caller gets nothing rather than trusting unannotated source.
+        assert_eq!(
+            find_module_in_site_package_path(
+                ModuleName::from_str("foo"),
+                &[root.to_path_buf()],
+                strict
+            ),
+            None
+        );
+        assert!(
+            find_module_in_site_package_path(
+                ModuleName::from_str("foo"),
+                &[root.to_path_buf()],
+                SitePackageResolverOptions::default(),
+            )
+            .is_some()
+        );
+    }
+
+    #[test]
+    fn test_require_py_typed_falls_back_to_stubs() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let root = tempdir.path();
+        TestPath::setup_test_directory(
+            root,
+            vec![
+                TestPath::dir("foo", vec![TestPath::file("__init__.py")]),
+                TestPath::dir(
+                    "foo-stubs",
+                    vec![TestPath::file("__init__.pyi"), TestPath::file("py.typed")],
+                ),
+            ],
+        );
+        let strict = SitePackageResolverOptions {
+            require_py_typed: true,
+        };
+        assert_eq!(
+            find_module_in_site_package_path(
+                ModuleName::from_str("foo"),
+                &[root.to_path_buf()],
+                strict
+            ),
+            Some(ResolvedModule {
+                path: ModulePath::filesystem(root.join("foo-stubs/__init__.pyi")),
+                py_typed: PyTyped::Complete,
+            })
+        );
+    }
+
+    #[test]
+    fn test_find_module_in_typeshed_respects_versions() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let root = tempdir.path();
+        TestPath::setup_test_directory(
+            root,
+            vec![TestPath::dir(
+                "stdlib",
+                vec![TestPath::file("tomllib.pyi"), TestPath::file("cgi.pyi")],
+            )],
+        );
+        let stdlib_versions = TypeshedVersions::parse("tomllib: 3.11-\ncgi: 3.2-3.12\n");
+        assert_eq!(
+            find_module_in_typeshed(
+                ModuleName::from_str("tomllib"),
+                PythonVersion::new(3, 10, 0),
+                root,
+                &stdlib_versions,
+            ),
+            None,
+        );
+        assert_eq!(
+            find_module_in_typeshed(
+                ModuleName::from_str("tomllib"),
+                PythonVersion::new(3, 11, 0),
+                root,
+                &stdlib_versions,
+            ),
+            Some(ModulePath::filesystem(root.join("stdlib/tomllib.pyi"))),
+        );
+        assert_eq!(
+            find_module_in_typeshed(
+                ModuleName::from_str("cgi"),
+                PythonVersion::new(3, 13, 0),
+                root,
+                &stdlib_versions,
+            ),
+            None,
+        );
+    }
+
+    #[test]
+    fn test_module_name_for_path_regular_module() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let root = tempdir.path();
+        assert_eq!(
+            module_name_for_path(&root.join("foo/bar.py"), &[root.to_path_buf()]),
+            Some(ModuleName::from_str("foo.bar"))
+        );
+    }
+
+    #[test]
+    fn test_module_name_for_path_drops_init() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let root = tempdir.path();
+        assert_eq!(
+            module_name_for_path(&root.join("foo/bar/__init__.pyi"), &[root.to_path_buf()]),
+            Some(ModuleName::from_str("foo.bar"))
+        );
+    }
+
+    #[test]
+    fn test_module_name_for_path_strips_stubs_suffix() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let root = tempdir.path();
+        assert_eq!(
+            module_name_for_path(&root.join("foo-stubs/bar.pyi"), &[root.to_path_buf()]),
+            Some(ModuleName::from_str("foo.bar"))
+        );
+    }
+
+    #[test]
+    fn test_module_name_for_path_rejects_invalid_identifier() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let root = tempdir.path();
+        assert_eq!(
+            module_name_for_path(&root.join("foo/bar-baz.py"), &[root.to_path_buf()]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_module_name_for_path_picks_longest_matching_root() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let root = tempdir.path();
+        let roots = [root.to_path_buf(), root.join("site-packages")];
+        assert_eq!(
+            module_name_for_path(&root.join("site-packages/foo.py"), &roots),
+            Some(ModuleName::from_str("foo"))
+        );
+    }
+
+    #[test]
+    fn test_module_name_for_path_no_matching_root() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let root = tempdir.path();
+        assert_eq!(
+            module_name_for_path(&root.join("other/foo.py"), &[root.join("site-packages")]),
+            None
         );
     }
 }
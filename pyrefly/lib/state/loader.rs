@@ -6,17 +6,22 @@
  */
 
 use std::fmt::Debug;
+use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
 
 use anyhow::anyhow;
 use dupe::Dupe;
+use starlark_map::small_map::SmallMap;
 
 use crate::config::config::ConfigFile;
+use crate::metadata::PythonVersion;
 use crate::module::module_name::ModuleName;
 use crate::module::module_path::ModulePath;
+use crate::module::module_path::ModulePathDetails;
 use crate::util::arc_id::ArcId;
 use crate::util::display::commas_iter;
+use crate::util::lock::Mutex;
 use crate::util::locked_map::LockedMap;
 
 #[derive(Debug, Clone, Dupe)]
@@ -28,6 +33,12 @@ pub enum FindError {
     /// This site package path entry was found, but does not have a py.typed entry
     /// and ignore_py_typed_package_errors is disabled
     NoPyTyped,
+    /// This module is a known standard library module, but typeshed's `VERSIONS` file
+    /// says it is not available on the configured target Python version
+    UnsupportedOnVersion {
+        module: ModuleName,
+        available: Arc<str>,
+    },
 }
 
 impl FindError {
@@ -38,6 +49,13 @@ impl FindError {
         Self::NotFound(Arc::new(err))
     }
 
+    pub fn unsupported_on_version(module: ModuleName, available: &str) -> Self {
+        Self::UnsupportedOnVersion {
+            module,
+            available: Arc::from(available),
+        }
+    }
+
     pub fn search_path(search_roots: &[PathBuf], site_package_path: &[PathBuf]) -> FindError {
         if search_roots.is_empty() && site_package_path.is_empty() {
             Self::not_found(anyhow!("no search roots or site package path"))
@@ -53,6 +71,180 @@ impl FindError {
     pub fn display(err: Arc<anyhow::Error>, module: ModuleName) -> String {
         format!("Could not find import of `{module}`, {:#}", err)
     }
+
+    /// Like `display`, but appends up to three "did you mean" suggestions for
+    /// similarly-named modules visible in the search roots.
+    ///
+    /// NOT WIRED UP: nothing in this tree calls this or constructs a `LoaderFindCache` to
+    /// source `suggestions` from (`LoaderId::find_import` calls straight through to
+    /// `ConfigFile::find_import` and `Config::compute_diagnostics` in `lsp.rs` builds its
+    /// messages from `Error::msg()`, not `FindError`). The error-collection pipeline that
+    /// would actually produce a `FindError` for an unresolved import and the `Transaction`
+    /// that would own a per-workspace `LoaderFindCache` both live outside the files in this
+    /// tree, so there's no call site here to wire this into. Covered by unit tests below
+    /// only; not exercised by a real unresolved-import diagnostic anywhere in this tree.
+    pub fn display_with_suggestions(
+        err: Arc<anyhow::Error>,
+        module: ModuleName,
+        suggestions: &[String],
+    ) -> String {
+        let mut message = Self::display(err, module);
+        if !suggestions.is_empty() {
+            message.push_str(&format!(
+                "\nDid you mean: {}?",
+                commas_iter(|| suggestions.iter().map(|x| format!("`{x}`")))
+            ));
+        }
+        message
+    }
+}
+
+/// Damerau-Levenshtein edit distance between `a` and `b` (unbounded; callers such as
+/// `rank_suggestions` apply their own threshold), counting
+/// insertions, deletions, substitutions, and adjacent transpositions.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    let (la, lb) = (a.len(), b.len());
+    let mut d = vec![vec![0usize; lb + 1]; la + 1];
+    for (i, row) in d.iter_mut().enumerate().take(la + 1) {
+        row[0] = i;
+    }
+    for j in 0..=lb {
+        d[0][j] = j;
+    }
+    for i in 1..=la {
+        for j in 1..=lb {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + cost);
+            }
+        }
+    }
+    d[la][lb]
+}
+
+/// Enumerate the top-level package/module names directly visible under `roots`:
+/// subdirectory names and `.py`/`.pyi` file stems.
+fn enumerate_candidates(roots: &[PathBuf]) -> Vec<String> {
+    let mut names = Vec::new();
+    for root in roots {
+        let Ok(entries) = std::fs::read_dir(root) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            let file_name = file_name.to_string_lossy();
+            if let Some(stem) = file_name
+                .strip_suffix(".pyi")
+                .or_else(|| file_name.strip_suffix(".py"))
+            {
+                names.push(stem.to_owned());
+            } else if entry.path().is_dir() {
+                names.push(file_name.into_owned());
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Rank `candidates` by edit distance to `missing`, keeping only close matches and
+/// returning at most three, sorted by distance then name.
+fn rank_suggestions(missing: &str, candidates: &[String]) -> Vec<String> {
+    let threshold = (missing.chars().count() / 3).max(2);
+    let mut scored = candidates
+        .iter()
+        .filter(|c| c.as_str() != missing)
+        .map(|c| (edit_distance(missing, c), c.clone()))
+        .filter(|(dist, _)| *dist <= threshold)
+        .collect::<Vec<_>>();
+    scored.sort_by(|(d1, n1), (d2, n2)| d1.cmp(d2).then_with(|| n1.cmp(n2)));
+    scored.into_iter().take(3).map(|(_, name)| name).collect()
+}
+
+/// An inclusive `major.minor` version range, as found in typeshed's `stdlib/VERSIONS` file.
+/// `max` is `None` when the entry's upper bound is unbounded (a trailing `-`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct VersionRange {
+    min: (u32, u32),
+    max: Option<(u32, u32)>,
+}
+
+impl VersionRange {
+    fn contains(&self, version: (u32, u32)) -> bool {
+        version >= self.min && self.max.is_none_or(|max| version <= max)
+    }
+
+    fn display(&self) -> String {
+        match self.max {
+            Some(max) => format!("{}.{}-{}.{}", self.min.0, self.min.1, max.0, max.1),
+            None => format!("{}.{}-", self.min.0, self.min.1),
+        }
+    }
+}
+
+fn parse_version_component(s: &str) -> Option<(u32, u32)> {
+    let (major, minor) = s.trim().split_once('.')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/// A parsed typeshed `stdlib/VERSIONS` file, mapping top-level (or explicitly-listed
+/// dotted) module names to the range of Python versions they're available on.
+#[derive(Debug, Clone, Default)]
+pub struct TypeshedVersions(SmallMap<String, VersionRange>);
+
+impl TypeshedVersions {
+    pub fn parse(contents: &str) -> Self {
+        let mut entries = SmallMap::new();
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((name, range)) = line.split_once(':') else {
+                continue;
+            };
+            let Some((min_str, max_str)) = range.trim().split_once('-') else {
+                continue;
+            };
+            let Some(min) = parse_version_component(min_str) else {
+                continue;
+            };
+            let max = if max_str.trim().is_empty() {
+                None
+            } else if let Some(max) = parse_version_component(max_str) {
+                Some(max)
+            } else {
+                continue;
+            };
+            entries.insert(name.trim().to_owned(), VersionRange { min, max });
+        }
+        Self(entries)
+    }
+
+    /// Look up the version range for `module`, preferring an explicit entry for the
+    /// full dotted name and falling back to the entry for its top-level package.
+    fn range_for(&self, module: ModuleName) -> Option<&VersionRange> {
+        self.0
+            .get(&module.to_string())
+            .or_else(|| self.0.get(module.first_component().as_str()))
+    }
+
+    /// Returns `Some(available)` (a human-readable range) if `module` is a known
+    /// stdlib module that is unavailable at `version`; `None` if it's available or unknown.
+    pub(crate) fn unsupported_at(&self, module: ModuleName, version: (u32, u32)) -> Option<String> {
+        let range = self.range_for(module)?;
+        if range.contains(version) {
+            None
+        } else {
+            Some(range.display())
+        }
+    }
 }
 
 #[derive(Clone, Dupe, Debug, Hash, PartialEq, Eq)]
@@ -72,10 +264,27 @@ impl LoaderId {
     }
 }
 
+/// NOT WIRED UP: nothing in this tree constructs a `LoaderFindCache` or calls `find_import`/
+/// `invalidate*`/`suggestions_for` on one. It's meant to sit between a per-workspace
+/// `Transaction` and a `LoaderId`, caching resolution and getting invalidated on
+/// `didChange`/`didSave`/watched-file events instead of the `Transaction`/`State` machinery
+/// (which isn't a file in this tree) recomputing resolution from scratch - but with no
+/// caller, this is unused surface area today, verified only by the unit tests below rather
+/// than by any real recheck or watched-file path.
 #[derive(Debug)]
 pub struct LoaderFindCache {
     loader: LoaderId,
     cache: LockedMap<ModuleName, Result<ModulePath, FindError>>,
+    /// Parsed typeshed `stdlib/VERSIONS` file, if one was configured. `None` means
+    /// resolution is not version-gated (e.g. no bundled typeshed is in use).
+    stdlib_versions: Option<TypeshedVersions>,
+    python_version: PythonVersion,
+    search_roots: Vec<PathBuf>,
+    site_package_path: Vec<PathBuf>,
+    /// Lazily-enumerated "did you mean" candidates, cleared by `invalidate_path`,
+    /// `invalidate_unresolved` and `set_suggestion_roots` so a newly installed package can
+    /// be suggested. `invalidate` (a single module) leaves them as they are.
+    candidates: Mutex<Option<Arc<Vec<String>>>>,
 }
 
 impl LoaderFindCache {
@@ -83,12 +292,517 @@ impl LoaderFindCache {
         Self {
             loader,
             cache: Default::default(),
+            stdlib_versions: None,
+            python_version: PythonVersion::default(),
+            search_roots: Vec::new(),
+            site_package_path: Vec::new(),
+            candidates: Mutex::new(None),
+        }
+    }
+
+    /// Like `new`, but gates stdlib resolution against a parsed `VERSIONS` file for
+    /// the given target Python version.
+    pub fn new_with_stdlib_versions(
+        loader: LoaderId,
+        stdlib_versions: TypeshedVersions,
+        python_version: PythonVersion,
+    ) -> Self {
+        Self {
+            loader,
+            cache: Default::default(),
+            stdlib_versions: Some(stdlib_versions),
+            python_version,
+            search_roots: Vec::new(),
+            site_package_path: Vec::new(),
+            candidates: Mutex::new(None),
         }
     }
 
+    /// Record the search roots and site-package paths to enumerate "did you mean"
+    /// suggestions from. Call this once after construction; it doesn't affect
+    /// resolution, only the candidates considered by `suggestions_for`.
+    pub fn set_suggestion_roots(
+        &mut self,
+        search_roots: Vec<PathBuf>,
+        site_package_path: Vec<PathBuf>,
+    ) {
+        self.search_roots = search_roots;
+        self.site_package_path = site_package_path;
+        *self.candidates.lock() = None;
+    }
+
+    /// Up to three module names visible in the configured roots that are close
+    /// (by edit distance) to `missing`'s final dotted component.
+    pub fn suggestions_for(&self, missing: ModuleName) -> Vec<String> {
+        let mut guard = self.candidates.lock();
+        let candidates = guard.get_or_insert_with(|| {
+            let mut names = enumerate_candidates(&self.search_roots);
+            names.extend(enumerate_candidates(&self.site_package_path));
+            names.sort();
+            names.dedup();
+            Arc::new(names)
+        });
+        let last_component = missing.components().last().map_or_else(
+            || missing.to_string(),
+            |name| name.as_str().to_owned(),
+        );
+        rank_suggestions(&last_component, candidates)
+    }
+
     pub fn find_import(&self, module: ModuleName) -> Result<ModulePath, FindError> {
         self.cache
-            .ensure(&module, || self.loader.find_import(module))
+            .ensure(&module, || {
+                if let Some(stdlib_versions) = &self.stdlib_versions
+                    && let Some(available) = stdlib_versions
+                        .unsupported_at(module, (self.python_version.major, self.python_version.minor))
+                {
+                    return Err(FindError::unsupported_on_version(module, &available));
+                }
+                self.loader.find_import(module)
+            })
             .dupe()
     }
+
+    /// Drop the cached result for `module`, forcing the next lookup to recompute it.
+    /// Use this when a specific module is known to have changed, e.g. from an LSP
+    /// `didChange`/`didSave` notification.
+    pub fn invalidate(&self, module: ModuleName) {
+        self.cache.remove(&module);
+    }
+
+    /// Drop every cached entry whose resolved `ModulePath` lives under `path`, or that
+    /// `path` itself lives under (e.g. a file created inside a namespace package's
+    /// directory), where `path` was just created, deleted, or modified on disk.
+    pub fn invalidate_path(&self, path: &Path) {
+        self.cache
+            .retain(|_, result| !matches!(result, Ok(resolved) if resolved_under(resolved, path)));
+        *self.candidates.lock() = None;
+    }
+
+    /// Drop every cached `NotFound`/`Ignored` entry, so a module that previously
+    /// failed to resolve (e.g. because a package wasn't installed yet) gets a fresh
+    /// lookup the next time it's imported.
+    pub fn invalidate_unresolved(&self) {
+        self.cache.retain(|_, result| result.is_ok());
+        *self.candidates.lock() = None;
+    }
+
+    /// Feed a batch of filesystem change events (paths that were created, deleted, or
+    /// modified) into the cache, invalidating the minimal set of entries they could
+    /// have affected. A file watcher should call this instead of clearing the cache
+    /// wholesale on every change.
+    pub fn invalidate_changed_paths(&self, changed_paths: &[PathBuf]) {
+        for path in changed_paths {
+            self.invalidate_path(path);
+        }
+        // A newly created file or directory might satisfy a module that previously
+        // failed to resolve, so always revisit negative results too.
+        self.invalidate_unresolved();
+    }
+}
+
+fn resolved_under(path: &ModulePath, ancestor: &Path) -> bool {
+    match path.details() {
+        ModulePathDetails::FileSystem(p)
+        | ModulePathDetails::Memory(p)
+        | ModulePathDetails::Namespace(p) => p.starts_with(ancestor) || ancestor.starts_with(p),
+        ModulePathDetails::BundledTypeshed(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::util::TestPath;
+
+    #[test]
+    fn test_edit_distance_basics() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("foo", "foo"), 0);
+        assert_eq!(edit_distance("foo", "foa"), 1);
+        assert_eq!(edit_distance("foo", "fo"), 1);
+        assert_eq!(edit_distance("foo", "food"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_edit_distance_transposition_counts_as_one() {
+        // Damerau-Levenshtein treats an adjacent swap as a single edit, unlike plain
+        // Levenshtein, which would need a delete and an insert (distance 2).
+        assert_eq!(edit_distance("requests", "reqeusts"), 1);
+    }
+
+    #[test]
+    fn test_rank_suggestions_orders_by_distance_then_name() {
+        let candidates = vec![
+            "requests".to_owned(),
+            "reqeusts".to_owned(),
+            "req".to_owned(),
+            "numpy".to_owned(),
+        ];
+        assert_eq!(
+            rank_suggestions("requsts", &candidates),
+            vec!["reqeusts".to_owned(), "requests".to_owned()],
+        );
+    }
+
+    #[test]
+    fn test_rank_suggestions_excludes_exact_match_and_far_names() {
+        let candidates = vec!["foo".to_owned(), "numpy".to_owned()];
+        assert_eq!(rank_suggestions("foo", &candidates), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_rank_suggestions_caps_at_three() {
+        let candidates = vec![
+            "fooa".to_owned(),
+            "foob".to_owned(),
+            "fooc".to_owned(),
+            "food".to_owned(),
+        ];
+        assert_eq!(rank_suggestions("foo", &candidates).len(), 3);
+    }
+
+    #[test]
+    fn test_enumerate_candidates_lists_packages_and_modules() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        TestPath::setup_test_directory(
+            root,
+            vec![
+                TestPath::dir("foo", vec![TestPath::file("__init__.py")]),
+                TestPath::file("bar.py"),
+                TestPath::file("baz.pyi"),
+                TestPath::file("README.md"),
+            ],
+        );
+        assert_eq!(
+            enumerate_candidates(&[root.to_path_buf()]),
+            vec!["bar".to_owned(), "baz".to_owned(), "foo".to_owned()],
+        );
+    }
+
+    #[test]
+    fn test_enumerate_candidates_ignores_missing_root() {
+        assert_eq!(
+            enumerate_candidates(&[PathBuf::from("/does/not/exist")]),
+            Vec::<String>::new(),
+        );
+    }
+
+    #[test]
+    fn test_suggestions_for_uses_configured_roots() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        TestPath::setup_test_directory(
+            root,
+            vec![TestPath::file("requests.py"), TestPath::file("numpy.py")],
+        );
+        let mut cache = LoaderFindCache::new(LoaderId::new(ConfigFile::default()));
+        cache.set_suggestion_roots(vec![root.to_path_buf()], Vec::new());
+        assert_eq!(
+            cache.suggestions_for(ModuleName::from_str("requsts")),
+            vec!["requests".to_owned()],
+        );
+    }
+
+    #[test]
+    fn test_display_with_suggestions() {
+        let err = Arc::new(anyhow!("not found"));
+        let module = ModuleName::from_str("requsts");
+        assert_eq!(
+            FindError::display_with_suggestions(err.dupe(), module, &[]),
+            FindError::display(err.dupe(), module),
+        );
+        assert_eq!(
+            FindError::display_with_suggestions(err.dupe(), module, &["requests".to_owned()]),
+            format!(
+                "{}\nDid you mean: `requests`?",
+                FindError::display(err, module)
+            ),
+        );
+    }
+
+    #[test]
+    fn test_typeshed_versions_parse_and_lookup() {
+        let versions = TypeshedVersions::parse(
+            "# this is a comment\n\
+             tomllib: 3.11-\n\
+             asyncio.staggered: 3.8-3.11  # inline comment\n\
+             \n\
+             ctypes: 3.0-3.9\n",
+        );
+        assert_eq!(
+            versions.unsupported_at(ModuleName::from_str("tomllib"), (3, 10)),
+            Some("3.11-".to_owned()),
+        );
+        assert_eq!(
+            versions.unsupported_at(ModuleName::from_str("tomllib"), (3, 11)),
+            None,
+        );
+        // An explicit entry for the full dotted name is used as is...
+        assert_eq!(
+            versions.unsupported_at(ModuleName::from_str("asyncio.staggered"), (3, 12)),
+            Some("3.8-3.11".to_owned()),
+        );
+        // ...while a dotted name with no entry of its own, whose top-level package has no
+        // entry either, is unknown.
+        assert_eq!(
+            versions.unsupported_at(ModuleName::from_str("asyncio.other"), (3, 9)),
+            None,
+        );
+        assert_eq!(
+            versions.unsupported_at(ModuleName::from_str("ctypes"), (3, 10)),
+            Some("3.0-3.9".to_owned()),
+        );
+        // Unknown modules are never considered unsupported.
+        assert_eq!(
+            versions.unsupported_at(ModuleName::from_str("unknown"), (3, 0)),
+            None,
+        );
+    }
+
+    #[test]
+    fn test_typeshed_versions_skips_malformed_lines() {
+        let versions = TypeshedVersions::parse(
+            "not_a_valid_line\n\
+             missing_range:\n\
+             bad_bound: nope-3.9\n\
+             tomllib: 3.11-\n",
+        );
+        assert_eq!(
+            versions.unsupported_at(ModuleName::from_str("tomllib"), (3, 10)),
+            Some("3.11-".to_owned()),
+        );
+        assert_eq!(
+            versions.unsupported_at(ModuleName::from_str("missing_range"), (3, 0)),
+            None,
+        );
+        assert_eq!(
+            versions.unsupported_at(ModuleName::from_str("bad_bound"), (3, 0)),
+            None,
+        );
+    }
+
+    #[test]
+    fn test_resolved_under_matches_in_either_direction() {
+        let path = ModulePath::filesystem(PathBuf::from("/root/foo/bar.py"));
+        // The resolved path is under the ancestor...
+        assert!(resolved_under(&path, Path::new("/root/foo")));
+        // ...an exact match counts too...
+        assert!(resolved_under(&path, Path::new("/root/foo/bar.py")));
+        assert!(!resolved_under(&path, Path::new("/root/other")));
+        // ...and the changed path can be under the resolved path instead (e.g. a file was
+        // created inside a directory that resolved as a namespace package).
+        let namespace = ModulePath::namespace(PathBuf::from("/root/ns"));
+        assert!(resolved_under(&namespace, Path::new("/root/ns/new.py")));
+    }
+
+    #[test]
+    fn test_resolved_under_covers_namespace_and_memory() {
+        let namespace = ModulePath::namespace(PathBuf::from("/root/ns/foo"));
+        assert!(resolved_under(&namespace, Path::new("/root/ns")));
+        assert!(!resolved_under(&namespace, Path::new("/root/other")));
+
+        let memory = ModulePath::memory(PathBuf::from("/root/mem/foo.py"));
+        assert!(resolved_under(&memory, Path::new("/root/mem")));
+        assert!(!resolved_under(&memory, Path::new("/root/other")));
+    }
+
+    #[test]
+    fn test_invalidate_drops_single_entry() {
+        let cache = LoaderFindCache::new(LoaderId::new(ConfigFile::default()));
+        let module = ModuleName::from_str("foo");
+        let calls = std::cell::Cell::new(0);
+        cache
+            .cache
+            .ensure(&module, || {
+                calls.set(calls.get() + 1);
+                Ok(ModulePath::filesystem(PathBuf::from("/root/foo.py")))
+            })
+            .dupe();
+        cache
+            .cache
+            .ensure(&module, || {
+                calls.set(calls.get() + 1);
+                Ok(ModulePath::filesystem(PathBuf::from("/root/foo.py")))
+            })
+            .dupe();
+        assert_eq!(calls.get(), 1, "second lookup should reuse the cached entry");
+
+        cache.invalidate(module);
+        cache
+            .cache
+            .ensure(&module, || {
+                calls.set(calls.get() + 1);
+                Ok(ModulePath::filesystem(PathBuf::from("/root/foo.py")))
+            })
+            .dupe();
+        assert_eq!(calls.get(), 2, "lookup after invalidate should recompute");
+    }
+
+    #[test]
+    fn test_invalidate_path_drops_only_entries_resolved_under_it() {
+        let cache = LoaderFindCache::new(LoaderId::new(ConfigFile::default()));
+        let foo = ModuleName::from_str("foo");
+        let bar = ModuleName::from_str("bar");
+        let foo_calls = std::cell::Cell::new(0);
+        let bar_calls = std::cell::Cell::new(0);
+
+        cache
+            .cache
+            .ensure(&foo, || {
+                foo_calls.set(foo_calls.get() + 1);
+                Ok(ModulePath::filesystem(PathBuf::from("/root/a/foo.py")))
+            })
+            .dupe();
+        cache
+            .cache
+            .ensure(&bar, || {
+                bar_calls.set(bar_calls.get() + 1);
+                Ok(ModulePath::filesystem(PathBuf::from("/root/b/bar.py")))
+            })
+            .dupe();
+
+        cache.invalidate_path(Path::new("/root/a"));
+
+        cache
+            .cache
+            .ensure(&foo, || {
+                foo_calls.set(foo_calls.get() + 1);
+                Ok(ModulePath::filesystem(PathBuf::from("/root/a/foo.py")))
+            })
+            .dupe();
+        cache
+            .cache
+            .ensure(&bar, || {
+                bar_calls.set(bar_calls.get() + 1);
+                Ok(ModulePath::filesystem(PathBuf::from("/root/b/bar.py")))
+            })
+            .dupe();
+
+        assert_eq!(foo_calls.get(), 2, "entry under the invalidated path should recompute");
+        assert_eq!(bar_calls.get(), 1, "entry under a different path should be untouched");
+    }
+
+    #[test]
+    fn test_invalidate_unresolved_drops_only_errors() {
+        let cache = LoaderFindCache::new(LoaderId::new(ConfigFile::default()));
+        let ok_module = ModuleName::from_str("foo");
+        let err_module = ModuleName::from_str("bar");
+        let ok_calls = std::cell::Cell::new(0);
+        let err_calls = std::cell::Cell::new(0);
+
+        cache
+            .cache
+            .ensure(&ok_module, || {
+                ok_calls.set(ok_calls.get() + 1);
+                Ok(ModulePath::filesystem(PathBuf::from("/root/foo.py")))
+            })
+            .dupe();
+        cache
+            .cache
+            .ensure(&err_module, || {
+                err_calls.set(err_calls.get() + 1);
+                Err(FindError::not_found(anyhow!("nope")))
+            })
+            .dupe();
+
+        cache.invalidate_unresolved();
+
+        cache
+            .cache
+            .ensure(&ok_module, || {
+                ok_calls.set(ok_calls.get() + 1);
+                Ok(ModulePath::filesystem(PathBuf::from("/root/foo.py")))
+            })
+            .dupe();
+        cache
+            .cache
+            .ensure(&err_module, || {
+                err_calls.set(err_calls.get() + 1);
+                Err(FindError::not_found(anyhow!("nope")))
+            })
+            .dupe();
+
+        assert_eq!(ok_calls.get(), 1, "a resolved entry should survive");
+        assert_eq!(err_calls.get(), 2, "an unresolved entry should be recomputed");
+    }
+
+    #[test]
+    fn test_invalidate_changed_paths_covers_overlapping_roots_and_unresolved() {
+        let cache = LoaderFindCache::new(LoaderId::new(ConfigFile::default()));
+        let under_a = ModuleName::from_str("under_a");
+        let under_nested = ModuleName::from_str("under_nested");
+        let elsewhere = ModuleName::from_str("elsewhere");
+        let missing = ModuleName::from_str("missing");
+        let calls = std::cell::Cell::new(0);
+
+        cache
+            .cache
+            .ensure(&under_a, || {
+                calls.set(calls.get() + 1);
+                Ok(ModulePath::filesystem(PathBuf::from("/root/a/x.py")))
+            })
+            .dupe();
+        // Two changed paths, one nested inside the other - both should still only
+        // invalidate what's actually resolved under them, not double-count.
+        cache
+            .cache
+            .ensure(&under_nested, || {
+                calls.set(calls.get() + 1);
+                Ok(ModulePath::filesystem(PathBuf::from("/root/a/b/y.py")))
+            })
+            .dupe();
+        cache
+            .cache
+            .ensure(&elsewhere, || {
+                calls.set(calls.get() + 1);
+                Ok(ModulePath::filesystem(PathBuf::from("/root/c/z.py")))
+            })
+            .dupe();
+        cache
+            .cache
+            .ensure(&missing, || {
+                calls.set(calls.get() + 1);
+                Err(FindError::not_found(anyhow!("nope")))
+            })
+            .dupe();
+        assert_eq!(calls.get(), 4);
+
+        cache.invalidate_changed_paths(&[PathBuf::from("/root/a"), PathBuf::from("/root/a/b")]);
+
+        cache
+            .cache
+            .ensure(&under_a, || {
+                calls.set(calls.get() + 1);
+                Ok(ModulePath::filesystem(PathBuf::from("/root/a/x.py")))
+            })
+            .dupe();
+        cache
+            .cache
+            .ensure(&under_nested, || {
+                calls.set(calls.get() + 1);
+                Ok(ModulePath::filesystem(PathBuf::from("/root/a/b/y.py")))
+            })
+            .dupe();
+        cache
+            .cache
+            .ensure(&elsewhere, || {
+                calls.set(calls.get() + 1);
+                Ok(ModulePath::filesystem(PathBuf::from("/root/c/z.py")))
+            })
+            .dupe();
+        cache
+            .cache
+            .ensure(&missing, || {
+                calls.set(calls.get() + 1);
+                Err(FindError::not_found(anyhow!("nope")))
+            })
+            .dupe();
+
+        // Both paths under `/root/a` recompute, `elsewhere` doesn't, and the
+        // previously-unresolved entry is revisited too.
+        assert_eq!(calls.get(), 4 + 3);
+    }
 }